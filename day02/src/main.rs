@@ -1,5 +1,7 @@
 use std::io;
 use std::io::prelude::*;
+use intcode::Program;
+use intcode::symbolic::{Solver, Symbol, Target};
 
 #[derive(PartialEq, Debug)]
 enum StepResult {
@@ -122,16 +124,16 @@ fn main() {
     println!("Part 1: Head == {} after run", &puzzle_part1.head());
 
     let target = 19690720;
-    for noun in 0..100 {
-        for verb in 0..100 {
-            let mut puzzle_xy = puzzle.clone();
-            puzzle_xy.set(noun, verb);
-            puzzle_xy.run();
-            if puzzle_xy.head() == target {
-                println!("Computed target {} with noun/verb {}", target, 10*noun + verb);
-                break
-            }
-        }
+    let memory: Vec<String> = puzzle.memory.iter().map(|x| x.to_string()).collect();
+    let mut solver = Solver::new(&Program::from_str(&memory.join(",")));
+    solver.symbolic(Symbol::Memory(1), 0..=99);
+    solver.symbolic(Symbol::Memory(2), 0..=99);
+    match solver.solve(Target::Memory(0, target)) {
+        Some(solution) => {
+            let (noun, verb) = (solution.values[0], solution.values[1]);
+            println!("Computed target {} with noun/verb {}", target, 10*noun + verb)
+        },
+        None => eprintln!("No noun/verb computes target {}", target)
     }
 }
 
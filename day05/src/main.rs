@@ -11,10 +11,10 @@ fn read_one_line_from_stdin() -> String {
 fn main() {
     let line = read_one_line_from_stdin();
     let mut program = Program::from_str(&line);
-    let return_code = program.run_and_print(&[1]);
+    let return_code = program.run_and_print(&[1]).unwrap();
     println!("Program (input=1) returned diagnostic code {}", return_code.unwrap());
     let mut program = Program::from_str(&line);
-    let return_code = program.run_and_print(&[5]);
+    let return_code = program.run_and_print(&[5]).unwrap();
     println!("Program (input=5) returned diagnostic code {}", return_code.unwrap());
 }
 
@@ -1,7 +1,8 @@
 use std::io;
 use std::io::prelude::*;
 
-use intcode::{Program, State};
+use intcode::Program;
+use intcode::network::{Cluster, Schedule};
 
 struct Permutations {
     k: u8,
@@ -55,46 +56,45 @@ impl Iterator for Permutations {
     }
 }
 
-fn amp_stack(program: &Program, perm: &Vec<u8>) -> Option<i64> {
-    let mut prev_return_code = 0;
-
-    for i in 0..5 {
-        let mut inputs = vec!();
-        inputs.push(perm[i] as i64);
-        inputs.push(prev_return_code);
-
-        let mut amp = program.clone();
+fn amplifiers(program: &Program, phases: &[i64]) -> Vec<Program> {
+    phases.iter().map(|&phase| {
+        let mut p = program.clone();
+        p.read_input(phase);
+        p
+    }).collect()
+}
 
-        if let Some(r) = amp.run(&inputs[..], &mut |_| {}) {
-            prev_return_code = r;
-        } else {
-            eprintln!("Program did not produce output on input {:?}", &inputs);
-            return None
+fn amp_stack(program: &Program, perm: &Vec<u8>) -> Option<i64> {
+    let phases: Vec<i64> = perm.iter().map(|&p| p as i64).collect();
+    let mut stack = Cluster::pipeline(amplifiers(program, &phases));
+    stack.node_mut(0).read_input(0);
+
+    match stack.run(Schedule::RoundRobin) {
+        Ok(outputs) => {
+            if outputs[4].is_empty() {
+                eprintln!("Program did not produce output on phases {:?}", &phases);
+            }
+            outputs[4].last().copied()
+        },
+        Err(e) => {
+            eprintln!("Amplifier {}", e);
+            None
         }
     }
-
-    Some(prev_return_code)
 }
 
 fn amp_stack_feeback(program: &Program, perm: &Vec<u8>) -> Option<i64> {
-    let mut amps: Vec<Program> = (0..5).map(|i| {
-        let mut p = program.clone();
-        p.read_input((perm[i] + 5) as i64);
-        p
-    }).collect();
-
-    let mut last_output = 0;
-    for i in std::iter::repeat(0..5).flatten() {
-        let amp = &mut amps[i];
-        amp.read_input(last_output);
-        let next_state = amp.await_output();
-        if let State::Output(out) | State::OutputAwaitingInput(out) = next_state {
-            last_output = out;
-        } else {
-            return Some(last_output)
+    let phases: Vec<i64> = perm.iter().map(|&p| (p + 5) as i64).collect();
+    let mut stack = Cluster::ring(amplifiers(program, &phases));
+    stack.node_mut(0).read_input(0);
+
+    match stack.run(Schedule::RoundRobin) {
+        Ok(outputs) => Some(outputs[4].last().copied().unwrap_or(0)),
+        Err(e) => {
+            eprintln!("Amplifier {}", e);
+            None
         }
     }
-    return Some(last_output)
 }
 
 fn best_amp_stack(program: &Program) -> i64 {
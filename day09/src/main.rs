@@ -3,8 +3,12 @@ use intcode::Program;
 fn main() {
     let line = util::read_single_line_from_stdin().unwrap();
     let program = Program::from_str(&line);
-    program.clone().run_and_print(&[1]);
+    if let Err(e) = program.clone().run_and_print(&[1]) {
+        eprintln!("BOOST program crashed: {}", e);
+    }
 
     println!("Locking on to Ceres...");
-    program.clone().run_and_print(&[2]);
+    if let Err(e) = program.clone().run_and_print(&[2]) {
+        eprintln!("BOOST program crashed: {}", e);
+    }
 }
@@ -76,33 +76,30 @@ impl Canvas {
             let current_white = self.white.contains(&self.xy.to_pair());
             self.program.read_input(current_white as i64);
 
-            let output = self.program.await_output();
-            match output {
-                intcode::State::Output(1) => {
-                    self.white.insert(self.xy.to_pair());
-                    p.insert(self.xy.to_pair());
+            // each move is a paint colour followed by a turn
+            match self.program.outputs().chunks(2).next() {
+                Some(Ok(pair)) => match pair[..] {
+                    [paint, turn] => {
+                        if paint == 1 {
+                            self.white.insert(self.xy.to_pair());
+                        } else {
+                            self.white.remove(&self.xy.to_pair());
+                        }
+                        p.insert(self.xy.to_pair());
+                        let next_heading = self.heading.turn(turn == 0);
+                        self.xy.incr(&next_heading);
+                        self.heading = next_heading;
+                    },
+                    _ => {
+                        eprintln!("Unexpected output {:?} from intcode!", &pair);
+                        break
+                    }
                 },
-                intcode::State::Output(0) => {
-                    self.white.remove(&self.xy.to_pair());
-                    p.insert(self.xy.to_pair());
-                },
-                other => {
-                    eprintln!("Unexpected output {:?} from intcode!", &other);
-                }
-            }
-
-            let output = self.program.await_output();
-            match output {
-                intcode::State::Output(x) | intcode::State::OutputAwaitingInput(x) => {
-                    let next_heading = self.heading.turn(x == 0);
-                    self.xy.incr(&next_heading);
-                    self.heading = next_heading;
-                },
-                intcode::State::Done => break,
-                other => {
-                    eprintln!("Unexpected output {:?} from intcode!", &other);
+                Some(Err(e)) => {
+                    eprintln!("Intcode program crashed: {}", e);
                     break
-                }
+                },
+                None => break
             }
         };
         p.len()
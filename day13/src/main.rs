@@ -11,7 +11,17 @@ fn sleep_one_second() {
 }
 
 enum Error {
-    IllegalStateError
+    IllegalStateError,
+    ProgramCrashed(intcode::Error)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Error::IllegalStateError => write!(writer, "Program behaved unexpectedly!"),
+            Error::ProgramCrashed(e) => write!(writer, "Intcode program crashed: {}", e)
+        }
+    }
 }
 
 enum Tile {
@@ -100,60 +110,31 @@ impl fmt::Display for Game {
 }
 
 fn play_single_move(game: &mut Game, program: &mut intcode::Program) -> Option<Error> {
-    loop {
-        let state1 = program.await_output();
-        match state1 {
-            intcode::State::AwaitingInput => return None,
-            intcode::State::Done => {
-                game.game_over = true;
-                return None
+    // the screen is drawn as (x, y, tile) triples, until the program wants a joystick move
+    for triple in program.outputs().chunks(3) {
+        match triple {
+            Ok(triple) => match triple[..] {
+                [-1, 0, score] => game.score = score,
+                [x, y, tile_code] => if let Some(tile) = Tile::from_int(tile_code) {
+                    game.tiles.insert((x,y), tile);
+                } else {
+                    eprintln!("{} does not code a valid tile type at ({},{})", tile_code, x, y)
+                },
+                _ => {
+                    game.game_over = true;
+                    return Some(Error::IllegalStateError)
+                }
             },
-            intcode::State::Crashed => {
+            Err(e) => {
                 game.game_over = true;
-                eprintln!("Intcode program crashed!");
-                return Some(Error::IllegalStateError)
-            },
-            intcode::State::Running => {
-                eprintln!("await_output() returned State::Running, this should never happen")
-            },
-            intcode::State::Output(x) | intcode::State::OutputAwaitingInput(x) => {
-                let state2 = program.await_output();
-                match state2 {
-                    intcode::State::AwaitingInput | intcode::State::Crashed | intcode::State::Done => {
-                        eprintln!("Program behaved unexpectedly!");
-                        game.game_over = true;
-                        return Some(Error::IllegalStateError)
-                    },
-                    intcode::State::Running => {
-                        eprintln!("await_output() returned State::Running, this should never happen")
-                    },
-                    intcode::State::Output(y) | intcode::State::OutputAwaitingInput(y) => {
-                        let state3 = program.await_output();
-                        match state3 {
-                            intcode::State::AwaitingInput | intcode::State::Crashed | intcode::State::Done => {
-                                eprintln!("Program behaved unexpectedly!");
-                                game.game_over = true;
-                                return Some(Error::IllegalStateError)
-                            },
-                            intcode::State::Running => {
-                                eprintln!("await_output() returned State::Running, this should never happen")
-                            },
-                            intcode::State::Output(tile_code) | intcode::State::OutputAwaitingInput(tile_code) => {
-                                match (x,y) {
-                                    (-1, 0) => game.score = tile_code,
-                                    _ => if let Some(tile) = Tile::from_int(tile_code) {
-                                        game.tiles.insert((x,y), tile);
-                                    } else {
-                                        eprintln!("{} does not code a valid tile type at ({},{})", tile_code, x, y)
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+                return Some(Error::ProgramCrashed(e))
             }
         }
     }
+    if program.is_terminated() {
+        game.game_over = true;
+    }
+    None
 }
 
 enum PlayerInput {
@@ -184,7 +165,9 @@ fn main() {
     let mut program = intcode::Program::from_str(&line);
     let mut game = Game::empty();
 
-    play_single_move(&mut game, &mut program);
+    if let Some(err) = play_single_move(&mut game, &mut program) {
+        eprintln!("{}", err);
+    }
 
     let mut block_count = 0;
     for (_, tile) in game.tiles.iter() {
@@ -200,7 +183,9 @@ fn main() {
     let line2 = pat.replace(&line, "2,");
     let mut program2 = intcode::Program::from_str(&line2);
     let mut game2 = Game::empty();
-    play_single_move(&mut game2, &mut program2);
+    if let Some(err) = play_single_move(&mut game2, &mut program2) {
+        eprintln!("{}", err);
+    }
     
     while !game2.game_over {
         if let Some((ball_x, paddle_x)) = game2.ball_and_paddle_pos() {
@@ -212,7 +197,9 @@ fn main() {
                 program2.read_input(PlayerInput::Neutral.to_int());
             }
 
-            play_single_move(&mut game2, &mut program2);
+            if let Some(err) = play_single_move(&mut game2, &mut program2) {
+                eprintln!("{}", err);
+            }
             println!("{}", &game2);
             sleep_one_second()
         } else {
@@ -111,10 +111,14 @@ impl RepairDroid {
                 self.program.read_input(next_direction.input_code());
                 let state = self.program.await_output();
                 let output_code = match state {
-                    State::Output(code) | State::OutputAwaitingInput(code) => code,
-                    _ => {
+                    Ok(State::Output(code)) | Ok(State::OutputAwaitingInput(code)) => code,
+                    Ok(state) => {
                         eprintln!("Unexpected state {:?}", state);
                         break
+                    },
+                    Err(e) => {
+                        eprintln!("Droid program crashed: {}", e);
+                        break
                     }
                 };
                 match output_code {
@@ -150,7 +154,10 @@ impl RepairDroid {
                             Direction::West
                         };
                         self.program.read_input(backtrack_direction.input_code());
-                        self.program.await_output();
+                        if let Err(e) = self.program.await_output() {
+                            eprintln!("Droid program crashed: {}", e);
+                            break
+                        }
                     }
                 }
             }
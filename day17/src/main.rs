@@ -1,9 +1,8 @@
-use std::convert::{TryFrom, Into};
-use std::char;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
-use intcode::{Program, State};
+use intcode::Program;
+use intcode::ascii::{self, AsciiMachine};
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct XY {
@@ -86,86 +85,50 @@ impl fmt::Display for Scaffold {
     }
 }
 
-fn read_ascii(program: &mut Program) -> Scaffold {
+fn read_ascii(program: Program) -> Result<Scaffold, ascii::Error> {
+    let text = AsciiMachine::new(program).read_text()?;
     let mut p = BTreeMap::new();
-    let mut x = 0;
-    let mut y = 0;
-
-    while let State::Output(c64) = program.await_output() {
-        for c32 in u32::try_from(c64) {
-            match char::from_u32(c32) {
-                None => {
-                    eprintln!("{} is not a valid character!", c32);
-                    x += 1;
-                },
-                Some('\n') => {
-                    x = 0;
-                    y += 1;
-                },
-                Some(c) => {
-                    p.insert(XY::new(x,y), c);
-                    x += 1;
-                }
-            }
+    for (y, line) in text.lines().enumerate() {
+        for (x, c) in line.chars().enumerate() {
+            p.insert(XY::new(x,y), c);
         }
     }
-    Scaffold { p }
+    Ok(Scaffold { p })
 }
 
-fn collect_dust(program: &mut Program, mmr: &str, a: &str, b: &str, c: &str) -> Option<i64> {
+fn collect_dust(mut program: Program, mmr: &str, a: &str, b: &str, c: &str) -> Result<Option<i64>, ascii::Error> {
     program.overwrite_memory(0, 2);
-    for chr in mmr.chars() {
-        program.read_input(chr as i64);
-    }
-    program.read_input('\n' as i64);
-    for chr in a.chars() {
-        program.read_input(chr as i64);
-    }
-    program.read_input('\n' as i64);
-    for chr in b.chars() {
-        program.read_input(chr as i64);
+    let mut robot = AsciiMachine::new(program);
+    for line in [mmr, a, b, c, "n"].iter() {
+        robot.send_line(line)?;
     }
-    program.read_input('\n' as i64);
-    for chr in c.chars() {
-        program.read_input(chr as i64);
-    }
-    program.read_input('\n' as i64);
-    program.read_input('n' as i64);
-    program.read_input('\n' as i64);
-
-    while let State::Output(out) = program.await_output() {
-        match as_ascii(out) {
-            Some(c) => print!("{}", c as char),
-            None => {
-                println!("");
-                println!("Non-character output: {}", out);
-                return Some(out)
-            }
-        }
-    };
-    return None
-}
-
-fn as_ascii(x: i64) -> Option<u8> {
-    u8::try_from(x).ok().filter(|u| u.is_ascii())
+    println!("{}", robot.read_text()?);
+    Ok(robot.result())
 }
 
 fn main() {
     let puzzle = util::read_single_line_from_stdin().unwrap();
     let program = Program::from_str(&puzzle);
-    let mut program1 = program.clone();
-    let map = read_ascii(&mut program1);
+    let map = match read_ascii(program.clone()) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Could not read the scaffold: {}", e);
+            return
+        }
+    };
     println!("{}", &map);
     let ac = map.alignment_checksum();
     println!("Alignment checksum: {}", &ac);
 
     // cheating here: I solved the second part by hand after printing the scaffolding to STDOUT, then piped my solution in
-    let mut program2 = program.clone();
     let main_movement_routine = util::read_single_line_from_stdin().unwrap();
     let movement_a = util::read_single_line_from_stdin().unwrap();
     let movement_b = util::read_single_line_from_stdin().unwrap();
     let movement_c = util::read_single_line_from_stdin().unwrap();
 
-    let dust = collect_dust(&mut program2, &main_movement_routine, &movement_a, &movement_b, &movement_c).unwrap();
-    println!("Dust collected: {}", dust);
+    match collect_dust(program, &main_movement_routine, &movement_a, &movement_b, &movement_c) {
+        Ok(Some(dust)) => println!("Dust collected: {}", dust),
+        Ok(None) => eprintln!("The robot did not report how much dust it collected"),
+        Err(e) => eprintln!("The robot failed: {}", e)
+    }
 }
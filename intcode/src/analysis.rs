@@ -0,0 +1,420 @@
+// Static analysis: basic blocks and a control-flow graph recovered by following execution from
+// address 0.
+//
+// Only jumps with immediate targets can be followed. A call is recognised as an immediate return
+// address pushed with `ADD #ret, #0, rb+k` just before an unconditional jump; the code at the
+// return address is then explored as well. A function is code that starts with `ARB #n` (its
+// prologue, with n > 0) and returns through `ARB #-n` followed by a jump to a relative-mode target.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::dialect::Dialect;
+use crate::disasm::{self, Item, Line};
+use crate::memory::Memory;
+use crate::{Instruction, ParameterMode, Program};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edge {
+    Fallthrough,
+    // Unconditional jump
+    Jump,
+    // Conditional jump, taken
+    Branch,
+    Call,
+    // From a call to the address it returns to
+    AfterCall
+}
+
+impl Edge {
+    fn label(&self) -> &'static str {
+        match self {
+            Edge::Fallthrough => "",
+            Edge::Jump => "jump",
+            Edge::Branch => "branch",
+            Edge::Call => "call",
+            Edge::AfterCall => "return"
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exit {
+    Halt,
+    // Falls or jumps to its successors
+    Continue,
+    // Jumps to a target that is only known at run time
+    Indirect,
+    // Runs into a word that does not decode as an instruction
+    Invalid
+}
+
+pub struct Block {
+    pub start: usize,
+    pub lines: Vec<Line>,
+    pub successors: Vec<(usize, Edge)>,
+    pub exit: Exit
+}
+
+impl Block {
+    // One past the last word of the block
+    pub fn end(&self) -> usize {
+        self.lines.last().map(|line| line.address + line.width()).unwrap_or(self.start)
+    }
+}
+
+pub struct Function {
+    pub entry: usize,
+    // Size of the stack frame set up by the prologue
+    pub frame: i64,
+    pub blocks: BTreeSet<usize>,
+    // Blocks that end in a matching epilogue
+    pub returns: Vec<usize>
+}
+
+// A write with a constant address into a word of discovered code
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SelfModifyingWrite {
+    pub instruction: usize,
+    pub target: usize
+}
+
+pub struct Analysis {
+    pub blocks: BTreeMap<usize, Block>,
+    pub functions: Vec<Function>,
+    pub self_modifying_writes: Vec<SelfModifyingWrite>
+}
+
+fn immediate(line: &Line, k: usize) -> Option<i64> {
+    match &line.item {
+        Item::Instruction(instruction, params) if instruction.parameter_modes()[k] == ParameterMode::Immediate => Some(params[k]),
+        _ => None
+    }
+}
+
+fn instruction(line: &Line) -> Option<Instruction> {
+    match &line.item {
+        Item::Instruction(instruction, _) => Some(*instruction),
+        Item::Data(_) => None
+    }
+}
+
+// The frame size of an `ARB #n` instruction
+fn frame_adjustment(line: &Line) -> Option<i64> {
+    match instruction(line) {
+        Some(Instruction::RelativeBaseAdjust { .. }) => immediate(line, 0),
+        _ => None
+    }
+}
+
+// The return address pushed by `ADD #ret, #0, rb+k` (or the same with the addends swapped)
+fn pushed_return_address(line: &Line) -> Option<usize> {
+    match (&line.item, instruction(line)) {
+        (Item::Instruction(_, params), Some(Instruction::Add { m1: ParameterMode::Immediate, m2: ParameterMode::Immediate, m3: ParameterMode::Relative })) => {
+            match (params[0], params[1]) {
+                (ret, 0) | (0, ret) if ret >= 0 => Some(ret as usize),
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+// Where a jump goes: whether it is always, never or sometimes taken, and its target if immediate
+fn jump(line: &Line) -> Option<(Option<bool>, Option<usize>)> {
+    let taken_when_nonzero = match instruction(line)? {
+        Instruction::JumpIfTrue { .. } => true,
+        Instruction::JumpIfFalse { .. } => false,
+        _ => return None
+    };
+    let taken = immediate(line, 0).map(|test| (test != 0) == taken_when_nonzero);
+    let target = immediate(line, 1).filter(|&t| t >= 0).map(|t| t as usize);
+    Some((taken, target))
+}
+
+pub fn analyse(memory: &[i64]) -> Analysis {
+    analyse_in(memory, None)
+}
+
+// Like analyse, also recognising a dialect's extension opcodes and modes; an extension
+// instruction is taken to fall through to the next
+pub fn analyse_in(memory: &[i64], dialect: Option<&Dialect>) -> Analysis {
+    let mut lines: BTreeMap<usize, Line> = BTreeMap::new();
+    let mut leaders: BTreeSet<usize> = BTreeSet::new();
+    let mut calls: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+    let mut pending = vec!(0);
+    leaders.insert(0);
+
+    // Find every reachable instruction, and the addresses where blocks must start
+    while let Some(address) = pending.pop() {
+        if address >= memory.len() || lines.contains_key(&address) {
+            continue
+        }
+        let line = disasm::decode_in(memory, address, dialect);
+        let next = address + line.width();
+        match (instruction(&line), jump(&line)) {
+            (None, _) | (Some(Instruction::Halt), _) => (),
+            (_, Some((taken, target))) => {
+                if let Some(target) = target.filter(|_| taken != Some(false)) {
+                    leaders.insert(target);
+                    pending.push(target);
+                }
+                if taken != Some(true) {
+                    leaders.insert(next);
+                    pending.push(next);
+                }
+                let previous = lines.range(..address).next_back().filter(|(a, l)| *a + l.width() == address);
+                if let (Some(true), Some(target), Some((_, previous))) = (taken, target, previous) {
+                    if let Some(ret) = pushed_return_address(previous) {
+                        calls.insert(address, (target, ret));
+                        leaders.insert(ret);
+                        pending.push(ret);
+                    }
+                }
+            },
+            _ => pending.push(next)
+        }
+        lines.insert(address, line);
+    }
+
+    // Split the instructions into blocks
+    let mut blocks = BTreeMap::new();
+    for &start in &leaders {
+        if !lines.contains_key(&start) {
+            continue
+        }
+        let mut block = Block { start, lines: vec!(), successors: vec!(), exit: Exit::Continue };
+        let mut address = start;
+        loop {
+            let line = match lines.remove(&address) {
+                Some(line) => line,
+                None => {
+                    // only reached when an earlier block already claimed the rest of this code
+                    block.successors.push((address, Edge::Fallthrough));
+                    break
+                }
+            };
+            let next = address + line.width();
+            let terminal = match (instruction(&line), jump(&line)) {
+                (None, _) => {
+                    block.exit = Exit::Invalid;
+                    true
+                },
+                (Some(Instruction::Halt), _) => {
+                    block.exit = Exit::Halt;
+                    true
+                },
+                (_, Some((taken, target))) => {
+                    match (calls.get(&address), target) {
+                        (Some(&(entry, ret)), _) => {
+                            block.successors.push((entry, Edge::Call));
+                            block.successors.push((ret, Edge::AfterCall));
+                        },
+                        (None, Some(target)) if taken == Some(true) => block.successors.push((target, Edge::Jump)),
+                        (None, Some(target)) if taken.is_none() => block.successors.push((target, Edge::Branch)),
+                        (None, None) if taken != Some(false) => block.exit = Exit::Indirect,
+                        _ => ()
+                    }
+                    if taken != Some(true) {
+                        block.successors.push((next, Edge::Fallthrough));
+                    }
+                    true
+                },
+                _ => false
+            };
+            block.lines.push(line);
+            if terminal {
+                break
+            }
+            if leaders.contains(&next) || next >= memory.len() {
+                block.successors.push((next, Edge::Fallthrough));
+                break
+            }
+            address = next;
+        }
+        block.successors.retain(|(to, _)| *to < memory.len());
+        if block.lines.is_empty() {
+            continue
+        }
+        blocks.insert(start, block);
+    }
+
+    let functions = find_functions(&blocks, &calls);
+    let self_modifying_writes = find_self_modifying_writes(&blocks);
+    Analysis { blocks, functions, self_modifying_writes }
+}
+
+fn find_functions(blocks: &BTreeMap<usize, Block>, calls: &BTreeMap<usize, (usize, usize)>) -> Vec<Function> {
+    let called: BTreeSet<usize> = calls.values().map(|(entry, _)| *entry).collect();
+    let mut functions = vec!();
+    for (&entry, block) in blocks {
+        let frame = match block.lines.first().and_then(frame_adjustment) {
+            Some(frame) if frame > 0 => frame,
+            _ => continue
+        };
+        // a prologue that is merely fallen into is not a function entry
+        let jumped_to = called.contains(&entry) || blocks.values().any(|b| b.successors.iter().any(|&(to, edge)| to == entry && edge != Edge::Fallthrough));
+        if !jumped_to {
+            continue
+        }
+
+        let mut body = BTreeSet::new();
+        let mut pending = vec!(entry);
+        while let Some(start) = pending.pop() {
+            if let Some(block) = blocks.get(&start) {
+                if body.insert(start) {
+                    pending.extend(block.successors.iter().filter(|(_, edge)| *edge != Edge::Call).map(|(to, _)| *to));
+                }
+            }
+        }
+        let returns = body.iter().copied().filter(|start| {
+            let lines = &blocks[start].lines;
+            blocks[start].exit == Exit::Indirect && lines.len() >= 2 &&
+                frame_adjustment(&lines[lines.len() - 2]) == Some(-frame) &&
+                matches!(lines[lines.len() - 1].item, Item::Instruction(ref i, _) if i.parameter_modes()[1] == ParameterMode::Relative)
+        }).collect();
+        functions.push(Function { entry, frame, blocks: body, returns });
+    }
+    functions
+}
+
+fn find_self_modifying_writes(blocks: &BTreeMap<usize, Block>) -> Vec<SelfModifyingWrite> {
+    let code: Vec<(usize, usize)> = blocks.values().map(|block| (block.start, block.end())).collect();
+    let mut writes = vec!();
+    for line in blocks.values().flat_map(|block| &block.lines) {
+        if let Item::Instruction(instruction, params) = &line.item {
+            let destination = match instruction {
+                Instruction::Input { m1: ParameterMode::Positional } => params[0],
+                Instruction::Add { m3: ParameterMode::Positional, .. } | Instruction::Mult { m3: ParameterMode::Positional, .. } |
+                Instruction::LessThan { m3: ParameterMode::Positional, .. } | Instruction::Equals { m3: ParameterMode::Positional, .. } => params[2],
+                _ => continue
+            };
+            if destination >= 0 && code.iter().any(|&(start, end)| (start..end).contains(&(destination as usize))) {
+                writes.push(SelfModifyingWrite { instruction: line.address, target: destination as usize });
+            }
+        }
+    }
+    writes
+}
+
+impl Analysis {
+    pub fn function_at(&self, entry: usize) -> Option<&Function> {
+        self.functions.iter().find(|f| f.entry == entry)
+    }
+
+    // Graphviz source; functions are drawn as clusters, and blocks that rewrite code in red
+    pub fn to_dot(&self) -> String {
+        let writers: BTreeSet<usize> = self.self_modifying_writes.iter().map(|w| w.instruction).collect();
+        let node = |block: &Block| {
+            let label: String = block.lines.iter().map(|line| format!("{}: {}\\l", line.address, line.text())).collect();
+            let exit = match block.exit {
+                Exit::Indirect => "indirect jump\\l",
+                Exit::Invalid => "invalid instruction\\l",
+                _ => ""
+            };
+            let color = if block.lines.iter().any(|line| writers.contains(&line.address)) { ", color=red" } else { "" };
+            format!("b{} [label=\"{}{}\"{}];", block.start, label, exit, color)
+        };
+
+        let mut dot = vec!("digraph intcode {".to_string(), "    node [shape=box, fontname=\"monospace\"];".to_string());
+        let mut drawn = BTreeSet::new();
+        for function in &self.functions {
+            dot.push(format!("    subgraph cluster_{} {{", function.entry));
+            dot.push(format!("        label=\"function {} (frame {})\";", function.entry, function.frame));
+            for start in &function.blocks {
+                if drawn.insert(*start) {
+                    dot.push(format!("        {}", node(&self.blocks[start])));
+                }
+            }
+            dot.push("    }".to_string());
+        }
+        for block in self.blocks.values().filter(|b| !drawn.contains(&b.start)) {
+            dot.push(format!("    {}", node(block)));
+        }
+        for block in self.blocks.values() {
+            for (to, edge) in &block.successors {
+                if !self.blocks.contains_key(to) {
+                    continue
+                }
+                match edge {
+                    Edge::Fallthrough => dot.push(format!("    b{} -> b{};", block.start, to)),
+                    Edge::AfterCall => dot.push(format!("    b{} -> b{} [label=\"{}\", style=dashed];", block.start, to, edge.label())),
+                    _ => dot.push(format!("    b{} -> b{} [label=\"{}\"];", block.start, to, edge.label()))
+                }
+            }
+        }
+        dot.push("}".to_string());
+        dot.join("\n")
+    }
+}
+
+impl<M: Memory> Program<M> {
+    pub fn analyse(&self) -> Analysis {
+        analyse_in(&self.image(), self.dialect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALLER: &str = "
+                ARB #stack
+                ADD #ret, #0, rb+0
+                JT #1, #double
+        ret:    ADD #result, #0, [patch+1]
+        patch:  OUT [0]
+                HLT
+        double: ARB #2
+                IN rb-1
+                MUL rb-1, #2, [result]
+                ARB #-2
+                JF #0, rb+0
+        result: DB 0
+        stack:  DB 0, 0, 0
+    ";
+
+    #[test]
+    fn blocks_test() {
+        let program = Program::assemble(CALLER).unwrap();
+        let analysis = program.analyse();
+        let starts: Vec<usize> = analysis.blocks.keys().copied().collect();
+        assert_eq!(starts, [0, 9, 16]);
+
+        assert_eq!(analysis.blocks[&0].successors, [(16, Edge::Call), (9, Edge::AfterCall)]);
+        assert_eq!(analysis.blocks[&9].exit, Exit::Halt);
+        assert_eq!(analysis.blocks[&16].exit, Exit::Indirect);
+        assert_eq!(analysis.blocks[&16].end(), 29);
+
+        assert_eq!(analysis.functions.len(), 1);
+        let double = analysis.function_at(16).unwrap();
+        assert_eq!(double.frame, 2);
+        assert_eq!(double.returns, [16]);
+
+        assert_eq!(analysis.self_modifying_writes, [SelfModifyingWrite { instruction: 9, target: 14 }]);
+        assert_eq!(program.clone().run(&[21], |_| ()), Ok(Some(42)));
+    }
+
+    #[test]
+    fn branch_test() {
+        // 0: IN [9]; 2: JF [9], #7; 5: OUT #1; 7: HLT; the HLT at 8 is never reached
+        let analysis = Program::from_str("3,9,1006,9,7,104,1,99,99,0").analyse();
+        assert_eq!(analysis.blocks[&0].successors, [(7, Edge::Branch), (5, Edge::Fallthrough)]);
+        assert_eq!(analysis.blocks[&5].successors, [(7, Edge::Fallthrough)]);
+        assert_eq!(analysis.blocks.len(), 3);
+        assert!(analysis.functions.is_empty());
+
+        let analysis = Program::from_str("1105,0,9,3,0,42").analyse();
+        assert_eq!(analysis.blocks[&0].successors, [(3, Edge::Fallthrough)]);
+        assert_eq!(analysis.blocks[&3].exit, Exit::Invalid);
+    }
+
+    #[test]
+    fn dot_test() {
+        let dot = Program::assemble(CALLER).unwrap().analyse().to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    subgraph cluster_16 {\n        label=\"function 16 (frame 2)\";\n        b16 [label=\"16: ARB #2\\l"));
+        assert!(dot.contains("indirect jump\\l\"];"));
+        assert!(dot.contains("b9 [label=\"9: ADD #29, #0, [14]\\l13: OUT [0]\\l15: HLT\\l\", color=red];"));
+        assert!(dot.contains("    b0 -> b16 [label=\"call\"];\n    b0 -> b9 [label=\"return\", style=dashed];"));
+    }
+}
@@ -0,0 +1,153 @@
+// Text I/O for ASCII-driven programs. Input is sent a line at a time; output is decoded until the
+// program stops to wait for input or halts. A program reports its answer as one final value
+// outside the ASCII range, which is kept apart from the text.
+
+use std::error;
+use std::fmt;
+
+use crate::{Program, State};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    // Output outside the ASCII range that was not the program's final value
+    InvalidCodePoint(i64),
+    NonAsciiInput(char),
+    Intcode(crate::Error)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidCodePoint(x) => write!(writer, "program output {} is not an ASCII character", x),
+            Error::NonAsciiInput(c) => write!(writer, "cannot send non-ASCII character {:?}", c),
+            Error::Intcode(e) => write!(writer, "{}", e)
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Error {
+        Error::Intcode(e)
+    }
+}
+
+pub struct AsciiMachine {
+    program: Program,
+    result: Option<i64>
+}
+
+impl AsciiMachine {
+    pub fn new(program: Program) -> AsciiMachine {
+        AsciiMachine { program, result: None }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn into_program(self) -> Program {
+        self.program
+    }
+
+    // Queues the line followed by a newline. Nothing is queued if the line is not all ASCII.
+    pub fn send_line(&mut self, line: &str) -> Result<(), Error> {
+        if let Some(c) = line.chars().find(|c| !c.is_ascii()) {
+            return Err(Error::NonAsciiInput(c))
+        }
+        for c in line.chars().chain(Some('\n')) {
+            self.program.read_input(c as i64);
+        }
+        Ok(())
+    }
+
+    // Runs until the program needs input or halts, and returns the text it printed
+    pub fn read_text(&mut self) -> Result<String, Error> {
+        let mut text = String::new();
+        loop {
+            match self.program.await_output()? {
+                State::Output(x) | State::OutputAwaitingInput(x) => match self.result {
+                    Some(result) => return Err(Error::InvalidCodePoint(result)),
+                    None if (0..128).contains(&x) => text.push(x as u8 as char),
+                    None => self.result = Some(x)
+                },
+                State::Running => continue,
+                State::AwaitingInput | State::Done => return Ok(text),
+                state @ State::OutOfFuel | state @ State::TimedOut | state @ State::InfiniteLoop(_) => return Err(Error::Intcode(self.program.interrupted(&state)))
+            }
+        }
+    }
+
+    pub fn result(&self) -> Option<i64> {
+        self.result
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.program.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Echoes each line back in upper case until it reads an empty line, then outputs 1000 plus the
+    // number of characters it has seen
+    const SHOUT: &str = "
+        .var c 0
+        .var count 1
+        .var tmp 2
+        .var prev 3
+                ARB #frame
+        read:   IN c
+                EQ c, #10, tmp
+                JF tmp, #char
+                EQ prev, #10, tmp
+                JT tmp, #done
+                OUT c
+                ADD c, #0, prev
+                JT #1, #read
+        char:   ADD c, #0, prev
+                ADD count, #1, count
+                LT c, #97, tmp
+                JT tmp, #print
+                ADD c, #-32, c
+        print:  OUT c
+                JT #1, #read
+        done:   ADD count, #1000, tmp
+                OUT tmp
+                HLT
+        frame:  DB 0, 0, 0, 10
+    ";
+
+    #[test]
+    fn conversation_test() {
+        let mut machine = AsciiMachine::new(Program::assemble(SHOUT).unwrap());
+        assert_eq!(machine.read_text(), Ok(String::new()));
+        machine.send_line("hello").unwrap();
+        machine.send_line("ascii world").unwrap();
+        assert_eq!(machine.read_text(), Ok("HELLO\nASCII WORLD\n".to_string()));
+        assert_eq!(machine.result(), None);
+        assert!(!machine.is_terminated());
+
+        machine.send_line("").unwrap();
+        assert_eq!(machine.read_text(), Ok(String::new()));
+        assert_eq!(machine.result(), Some(1016));
+        assert!(machine.is_terminated());
+    }
+
+    #[test]
+    fn error_test() {
+        let mut machine = AsciiMachine::new(Program::from_str("104,1000,104,65,99"));
+        assert_eq!(machine.read_text(), Err(Error::InvalidCodePoint(1000)));
+
+        let mut machine = AsciiMachine::new(Program::from_str("3,0,99"));
+        assert_eq!(machine.send_line("café"), Err(Error::NonAsciiInput('é')));
+        assert!(machine.program().input_buffer().is_empty());
+
+        let mut machine = AsciiMachine::new(Program::from_str("104,65,42"));
+        let err = machine.read_text().unwrap_err();
+        assert_eq!(err.to_string(), "unknown opcode (instruction 42 at 2)");
+    }
+}
@@ -0,0 +1,505 @@
+// A small assembly language for intcode. Each line holds an optional label, then either an
+// instruction, a data directive or a variable definition; `;` starts a comment.
+//
+//   .var n 0                ; rb+0 can now be written as `n`
+//           ARB #stack
+//           IN n
+//   loop:   ADD [total], n, [total]
+//           ADD n, #-1, n
+//           JT n, #loop
+//           OUT [total]
+//           HLT
+//   total:  DB 0
+//   stack:  DB 0
+//
+// Operands are `[addr]` (positional), `#value` (immediate) or `rb+offset` (relative), where
+// addresses, values and offsets may be integers, labels or variables, plus or minus integers.
+// A numeric label such as `12:` asserts the current address, so disassembler listings reassemble.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use crate::{Instruction, ParameterMode, Program};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    UnknownMnemonic(String),
+    OperandCount { expected: usize, found: usize },
+    BadOperand(String),
+    BadDirective(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    AddressMismatch { expected: usize, found: usize },
+    // An expression whose value does not fit in a word
+    Overflow
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Error {
+    pub line: usize,
+    pub kind: ErrorKind
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        write!(writer, "line {}: ", self.line)?;
+        match &self.kind {
+            ErrorKind::UnknownMnemonic(m) => write!(writer, "unknown mnemonic {}", m),
+            ErrorKind::OperandCount { expected, found } => write!(writer, "expected {} operands, found {}", expected, found),
+            ErrorKind::BadOperand(op) => write!(writer, "cannot parse operand {}", op),
+            ErrorKind::BadDirective(d) => write!(writer, "cannot parse directive {}", d),
+            ErrorKind::UndefinedSymbol(s) => write!(writer, "undefined symbol {}", s),
+            ErrorKind::DuplicateSymbol(s) => write!(writer, "symbol {} is defined more than once", s),
+            ErrorKind::AddressMismatch { expected, found } => write!(writer, "address label {} found at address {}", expected, found),
+            ErrorKind::Overflow => write!(writer, "expression does not fit in a word")
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+enum Symbol {
+    Label(usize),
+    Var(i64)
+}
+
+enum Atom {
+    Number(i64),
+    Name(String)
+}
+
+// A sum of signed atoms, e.g. `loop+2` or `-7`
+struct Expr {
+    terms: Vec<(bool, Atom)>
+}
+
+impl Expr {
+    fn parse(s: &str) -> Option<Expr> {
+        let mut terms = vec!();
+        let mut rest = s.trim();
+        let mut negative = false;
+        if let Some(r) = rest.strip_prefix('-') {
+            negative = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('+') {
+            rest = r;
+        }
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let atom = rest[..end].trim();
+            if atom.is_empty() {
+                return None
+            }
+            // the sign is parsed with the digits, so that -2^63 is a number
+            if let Ok(n) = format!("{}{}", if negative { "-" } else { "" }, atom).parse::<i64>() {
+                terms.push((false, Atom::Number(n)));
+            } else if is_identifier(atom) {
+                terms.push((negative, Atom::Name(atom.to_string())));
+            } else {
+                return None
+            }
+            match rest[end..].chars().next() {
+                None => return Some(Expr { terms }),
+                Some(sign) => {
+                    negative = sign == '-';
+                    rest = &rest[end + 1..];
+                }
+            }
+        }
+    }
+
+    fn first_name(&self) -> Option<&str> {
+        match self.terms.first() {
+            Some((false, Atom::Name(name))) => Some(name),
+            _ => None
+        }
+    }
+
+    fn eval(&self, symbols: &HashMap<String, Symbol>) -> Result<i64, ErrorKind> {
+        self.terms.iter().try_fold(0i64, |acc, (negative, atom)| {
+            let value = match atom {
+                Atom::Number(n) => *n,
+                Atom::Name(name) => match symbols.get(name) {
+                    Some(Symbol::Label(address)) => *address as i64,
+                    Some(Symbol::Var(offset)) => *offset,
+                    None => return Err(ErrorKind::UndefinedSymbol(name.clone()))
+                }
+            };
+            let sum = if *negative { acc.checked_sub(value) } else { acc.checked_add(value) };
+            sum.ok_or(ErrorKind::Overflow)
+        })
+    }
+}
+
+enum Operand {
+    Positional(Expr),
+    Immediate(Expr),
+    Relative(Expr),
+    Bare(Expr)
+}
+
+impl Operand {
+    fn parse(s: &str) -> Option<Operand> {
+        let s = s.trim();
+        if s.starts_with('[') && s.ends_with(']') {
+            Expr::parse(&s[1..s.len() - 1]).map(Operand::Positional)
+        } else if let Some(rest) = s.strip_prefix('#') {
+            Expr::parse(rest).map(Operand::Immediate)
+        } else if s == "rb" {
+            Some(Operand::Relative(Expr { terms: vec!((false, Atom::Number(0))) }))
+        } else if s.starts_with("rb+") || s.starts_with("rb-") {
+            Expr::parse(&s[2..]).map(Operand::Relative)
+        } else {
+            Expr::parse(s).map(Operand::Bare)
+        }
+    }
+
+    // A bare operand is only meaningful when it names a relative-base variable
+    fn resolve(&self, symbols: &HashMap<String, Symbol>, text: &str) -> Result<(ParameterMode, i64), ErrorKind> {
+        match self {
+            Operand::Positional(expr) => expr.eval(symbols).map(|x| (ParameterMode::Positional, x)),
+            Operand::Immediate(expr) => expr.eval(symbols).map(|x| (ParameterMode::Immediate, x)),
+            Operand::Relative(expr) => expr.eval(symbols).map(|x| (ParameterMode::Relative, x)),
+            Operand::Bare(expr) => match expr.first_name().and_then(|name| symbols.get(name)) {
+                Some(Symbol::Var(_)) => expr.eval(symbols).map(|x| (ParameterMode::Relative, x)),
+                _ => Err(ErrorKind::BadOperand(text.to_string()))
+            }
+        }
+    }
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<(String, Operand)> },
+    Data(Vec<Datum>)
+}
+
+enum Datum {
+    Word(Expr),
+    Text(String)
+}
+
+impl Statement {
+    fn width(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => 1 + operands.len(),
+            Statement::Data(data) => data.iter().map(|datum| match datum {
+                Datum::Word(_) => 1,
+                Datum::Text(text) => text.chars().count()
+            }).sum()
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false) &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn arity(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "HLT" => Some(0),
+        "IN" | "OUT" | "ARB" => Some(1),
+        "JT" | "JF" => Some(2),
+        "ADD" | "MUL" | "LT" | "EQ" => Some(3),
+        _ => None
+    }
+}
+
+fn instruction(mnemonic: &str, m: &[ParameterMode]) -> Instruction {
+    match mnemonic {
+        "ADD" => Instruction::Add { m1: m[0], m2: m[1], m3: m[2] },
+        "MUL" => Instruction::Mult { m1: m[0], m2: m[1], m3: m[2] },
+        "IN" => Instruction::Input { m1: m[0] },
+        "OUT" => Instruction::Output { m1: m[0] },
+        "JT" => Instruction::JumpIfTrue { m1: m[0], m2: m[1] },
+        "JF" => Instruction::JumpIfFalse { m1: m[0], m2: m[1] },
+        "LT" => Instruction::LessThan { m1: m[0], m2: m[1], m3: m[2] },
+        "EQ" => Instruction::Equals { m1: m[0], m2: m[1], m3: m[2] },
+        "ARB" => Instruction::RelativeBaseAdjust { m1: m[0] },
+        _ => Instruction::Halt
+    }
+}
+
+// Splits on commas that are not inside a string literal
+fn split_operands(s: &str) -> Vec<String> {
+    let mut parts = vec!();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue
+            },
+            _ => ()
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !parts.is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..idx],
+            _ => ()
+        }
+    }
+    line
+}
+
+fn parse_text(s: &str) -> Option<String> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next()? {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                c @ '\\' | c @ '"' => text.push(c),
+                _ => return None
+            }
+        } else {
+            text.push(c);
+        }
+    }
+    Some(text)
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, Error> {
+    let mut symbols: HashMap<String, Symbol> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = vec!();
+    let mut address = 0;
+
+    for (idx, raw_line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+        let err = |kind| Error { line: line_number, kind };
+        let mut line = strip_comment(raw_line).trim();
+
+        while let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if let Ok(expected) = label.parse::<usize>() {
+                if expected != address {
+                    return Err(err(ErrorKind::AddressMismatch { expected, found: address }))
+                }
+            } else if is_identifier(label) {
+                if symbols.insert(label.to_string(), Symbol::Label(address)).is_some() {
+                    return Err(err(ErrorKind::DuplicateSymbol(label.to_string())))
+                }
+            } else {
+                break
+            }
+            line = line[colon + 1..].trim();
+        }
+        if line.is_empty() {
+            continue
+        }
+
+        let (head, rest) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, "")
+        };
+        let mnemonic = head.to_ascii_uppercase();
+        let statement = if mnemonic == ".VAR" {
+            let tokens: Vec<&str> = rest.split_whitespace().collect();
+            match tokens[..] {
+                [name, offset] if is_identifier(name) => match offset.parse::<i64>() {
+                    Ok(offset) => {
+                        if symbols.insert(name.to_string(), Symbol::Var(offset)).is_some() {
+                            return Err(err(ErrorKind::DuplicateSymbol(name.to_string())))
+                        }
+                        continue
+                    },
+                    Err(_) => return Err(err(ErrorKind::BadDirective(line.to_string())))
+                },
+                _ => return Err(err(ErrorKind::BadDirective(line.to_string())))
+            }
+        } else if mnemonic == "DB" {
+            let mut data = vec!();
+            for part in split_operands(rest) {
+                if part.starts_with('"') {
+                    data.push(Datum::Text(parse_text(&part).ok_or_else(|| err(ErrorKind::BadOperand(part.clone())))?));
+                } else {
+                    data.push(Datum::Word(Expr::parse(&part).ok_or_else(|| err(ErrorKind::BadOperand(part.clone())))?));
+                }
+            }
+            Statement::Data(data)
+        } else {
+            let expected = arity(&mnemonic).ok_or_else(|| err(ErrorKind::UnknownMnemonic(head.to_string())))?;
+            let parts = split_operands(rest);
+            if parts.len() != expected {
+                return Err(err(ErrorKind::OperandCount { expected, found: parts.len() }))
+            }
+            let mut operands = vec!();
+            for part in parts {
+                let operand = Operand::parse(&part).ok_or_else(|| err(ErrorKind::BadOperand(part.clone())))?;
+                operands.push((part, operand));
+            }
+            Statement::Instruction { mnemonic, operands }
+        };
+        address += statement.width();
+        statements.push((line_number, statement));
+    }
+
+    let mut memory = Vec::with_capacity(address);
+    for (line_number, statement) in statements {
+        let err = |kind| Error { line: line_number, kind };
+        match statement {
+            Statement::Data(data) => {
+                for datum in data {
+                    match datum {
+                        Datum::Word(expr) => memory.push(expr.eval(&symbols).map_err(err)?),
+                        Datum::Text(text) => memory.extend(text.chars().map(|c| c as i64))
+                    }
+                }
+            },
+            Statement::Instruction { mnemonic, operands } => {
+                let mut modes = vec!();
+                let mut params = vec!();
+                for (text, operand) in operands {
+                    let (mode, param) = operand.resolve(&symbols, &text).map_err(err)?;
+                    modes.push(mode);
+                    params.push(param);
+                }
+                memory.push(instruction(&mnemonic, &modes).encode());
+                memory.extend(params);
+            }
+        }
+    }
+    Ok(memory)
+}
+
+// Assembles to the comma-separated format read by `Program::from_str`
+pub fn compile(source: &str) -> Result<String, Error> {
+    assemble(source).map(|memory| {
+        memory.iter().map(|word| word.to_string()).collect::<Vec<String>>().join(",")
+    })
+}
+
+impl Program {
+    pub fn assemble(source: &str) -> Result<Program, Error> {
+        compile(source).map(|line| Program::from_str(&line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    #[test]
+    fn fixture_test() {
+        let source = "
+                    IN [x]
+                    EQ [x], [eight], [x]
+                    OUT [x]
+                    HLT
+            x:      DB -1
+            eight:  DB 8
+        ";
+        assert_eq!(compile(source), Ok("3,9,8,9,10,9,4,9,99,-1,8".to_string()));
+
+        let source = "
+                    IN [x+1]
+            x:      LT #-1, #8, [x+1]       ; the input overwrites the first operand
+                    OUT [x+1]
+                    HLT
+        ";
+        assert_eq!(compile(source), Ok("3,3,1107,-1,8,3,4,3,99".to_string()));
+    }
+
+    #[test]
+    fn variable_test() {
+        let source = "
+            .var n 0
+            .var total 1
+                    ARB #stack
+                    IN n
+            loop:   ADD total, n, total
+                    ADD rb+n, #-1, n
+                    JT n, #loop
+                    OUT rb+1
+                    HLT
+            stack:  DB 0, 0
+        ";
+        let mut program = Program::assemble(source).unwrap();
+        let mut outputs = vec!();
+        program.run(&[4], |x| outputs.push(x)).unwrap();
+        assert_eq!(outputs[..], [10]);
+    }
+
+    #[test]
+    fn data_test() {
+        let source = r#"
+            start:  DB "hi; \"there\"\n", start, end-1, -3
+            end:
+        "#;
+        let expected: Vec<i64> = "hi; \"there\"\n".chars().map(|c| c as i64).chain(vec!(0, 14, -3)).collect();
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let fixtures = [
+            "1,9,10,3,2,3,11,0,99,30,40,50",
+            "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9",
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+            "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+            "1102,34915192,34915192,7,4,7,99,0",
+            "104,1125899906842624,99",
+            "42,1003,-7,11101,1,1",
+            "104,-9223372036854775808,99,-9223372036854775808,9223372036854775807"
+        ];
+        for fixture in fixtures.iter() {
+            let listing = Program::from_str(fixture).disassemble();
+            assert_eq!(compile(&listing).as_deref(), Ok(*fixture));
+        }
+    }
+
+    #[test]
+    fn run_test() {
+        let source = "
+                    OUT #72
+                    OUT #105
+                    JT #1, #done
+                    OUT #0
+            done:   HLT
+        ";
+        let mut program = Program::assemble(source).unwrap();
+        assert_eq!(program.await_output(), Ok(State::Output(72)));
+        assert_eq!(program.await_output(), Ok(State::Output(105)));
+        assert_eq!(program.await_output(), Ok(State::Done));
+    }
+
+    #[test]
+    fn error_test() {
+        let e = |line, kind| Err(Error { line, kind });
+        assert_eq!(assemble("FOO #1"), e(1, ErrorKind::UnknownMnemonic("FOO".to_string())));
+        assert_eq!(assemble("\nADD #1, #2"), e(2, ErrorKind::OperandCount { expected: 3, found: 2 }));
+        assert_eq!(assemble("OUT [x"), e(1, ErrorKind::BadOperand("[x".to_string())));
+        assert_eq!(assemble("OUT 5"), e(1, ErrorKind::BadOperand("5".to_string())));
+        assert_eq!(assemble("OUT [nowhere]"), e(1, ErrorKind::UndefinedSymbol("nowhere".to_string())));
+        assert_eq!(assemble("a: HLT\na: HLT"), e(2, ErrorKind::DuplicateSymbol("a".to_string())));
+        assert_eq!(assemble("HLT\n0: HLT"), e(2, ErrorKind::AddressMismatch { expected: 0, found: 1 }));
+        assert_eq!(assemble(".var x"), e(1, ErrorKind::BadDirective(".var x".to_string())));
+        assert_eq!(assemble("x: DB 9223372036854775807 + x + 1"), e(1, ErrorKind::Overflow));
+        assert_eq!(assemble("DB -9223372036854775808 - 1"), e(1, ErrorKind::Overflow));
+        assert_eq!(assemble("DB 5 - 9223372036854775808, -9223372036854775808"), Ok(vec!(i64::MIN + 5, i64::MIN)));
+    }
+}
@@ -0,0 +1,20 @@
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+use intcode::asm;
+
+fn main() {
+    let mut source = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut source) {
+        eprintln!("Error reading assembly from stdin: {}", e);
+        process::exit(1);
+    }
+    match asm::compile(&source) {
+        Ok(line) => println!("{}", line),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
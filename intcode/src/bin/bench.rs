@@ -0,0 +1,75 @@
+// Times the interpreter with and without the decoded-instruction cache.
+//
+//   cargo run --release --bin bench -- <program file>
+//
+// The program (e.g. the day 9 BOOST program) is run once with input 1 and once with input 2.
+// Without a file, a built-in loop stands in for it.
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant};
+
+use intcode::Program;
+
+const REPEATS: usize = 5;
+
+// Counts down from 500000 times its input, moving the relative base on every iteration
+const WORKLOAD: &str = "
+        IN [n]
+        MUL [n], #500000, [n]
+loop:   ADD [n], #-1, [n]
+        ARB #1
+        JT [n], #loop
+        OUT [n]
+        HLT
+n:      DB 0
+";
+
+// Best of a few runs, and the program's final output
+fn time(program: &Program, input: i64, cached: bool) -> (Duration, Option<i64>) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut result = None;
+    for _ in 0..REPEATS {
+        let mut program = program.clone();
+        program.set_instruction_cache(cached);
+        let start = Instant::now();
+        let outcome = program.run(&[input], |_| ());
+        best = best.min(start.elapsed());
+        result = match outcome {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("Program crashed on input {}: {}", input, e);
+                process::exit(1);
+            }
+        };
+    }
+    (best, result)
+}
+
+fn main() {
+    let program = match env::args().nth(1) {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(source) => Program::from_str(source.trim()),
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => {
+            println!("No program given; timing a built-in loop");
+            Program::assemble(WORKLOAD).expect("built-in workload assembles")
+        }
+    };
+
+    for &input in [1, 2].iter() {
+        let (uncached, expected) = time(&program, input, false);
+        let (cached, result) = time(&program, input, true);
+        if result != expected {
+            eprintln!("Cached run disagrees on input {}: {:?} vs {:?}", input, result, expected);
+            process::exit(1);
+        }
+        println!("input {}: output {:?}; uncached {:?}, cached {:?}, speedup {:.2}x",
+            input, result, uncached, cached, uncached.as_secs_f64() / cached.as_secs_f64());
+    }
+}
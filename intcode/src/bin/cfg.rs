@@ -0,0 +1,19 @@
+use std::io;
+use std::io::prelude::*;
+
+use intcode::Program;
+
+// Reads a program from stdin and prints its control-flow graph in Graphviz format
+fn main() {
+    let stdin = io::stdin();
+    match stdin.lock().lines().next().and_then(|line| line.ok()) {
+        Some(line) => {
+            let analysis = Program::from_str(&line).analyse();
+            for write in &analysis.self_modifying_writes {
+                eprintln!("Instruction at {} writes to code at {}", write.instruction, write.target);
+            }
+            println!("{}", analysis.to_dot());
+        },
+        None => eprintln!("Error reading program from stdin!")
+    }
+}
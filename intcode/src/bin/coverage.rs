@@ -0,0 +1,50 @@
+// Runs an intcode program once per input list and prints its listing annotated with coverage.
+//
+//   cargo run --bin coverage -- <program file> [inputs ...]
+//
+// Each argument after the program is the comma-separated input for one run, e.g. `1 5` runs the
+// day 5 diagnostic twice; with none, the program runs once without input.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::coverage::Coverage;
+use intcode::Program;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, runs) = match args.split_first() {
+        Some((path, runs)) => (path, runs),
+        None => {
+            eprintln!("usage: coverage <program file> [inputs ...]");
+            process::exit(1);
+        }
+    };
+    let runs: Vec<Vec<i64>> = match runs.iter()
+        .map(|run| run.split(',').filter(|s| !s.is_empty()).map(|s| s.trim().parse()).collect())
+        .collect() {
+        Ok(runs) => runs,
+        Err(e) => {
+            eprintln!("Inputs must be comma-separated integers: {}", e);
+            process::exit(1);
+        }
+    };
+    let runs = if runs.is_empty() { vec!(vec!()) } else { runs };
+    let program = match fs::read_to_string(path) {
+        Ok(source) => Program::from_str(source.trim()),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut coverage = Coverage::new(&program);
+    for inputs in &runs {
+        match program.clone().run_observed(inputs, |_| (), &mut coverage) {
+            Ok(result) => eprintln!("{:?}: finished with output {:?}", inputs, result),
+            Err(e) => eprintln!("{:?}: stopped: {}", inputs, e)
+        }
+    }
+    println!("{}", coverage.listing());
+}
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+use intcode::{Error, Program, State};
+use intcode::history::History;
+
+const HELP: &str = "\
+step [n]          (s)  execute n instructions (default 1)
+continue          (c)  run until a breakpoint, watchpoint, halt or missing input
+break <addr>      (b)  set a breakpoint
+watch <addr>      (w)  stop whenever the value at addr changes
+delete <addr>     (d)  remove a breakpoint or watchpoint
+info              (i)  list breakpoints and watchpoints
+regs              (r)  show instruction pointer, relative base and queued input
+mem <addr> [n]    (x)  dump n memory cells (default 8)
+list [addr] [n]   (l)  disassemble n instructions (default 5) from addr (default ip)
+back [n]               undo the last n steps (default 1)
+rewind <addr>          undo steps until just before the last execution of the instruction at addr
+writer <addr>          show the step that last wrote to addr
+input <v> [v...]       queue integer input
+ascii <text>           queue text as ASCII input, followed by a newline
+save <file>            write a snapshot of the machine
+load <file>            resume from a snapshot; breakpoints and watchpoints are kept
+quit              (q)  exit";
+
+enum Stop {
+    Breakpoint(usize),
+    Watchpoint { address: usize, old: i64, new: i64 },
+    AwaitingInput,
+    Halted,
+    Crashed(Error)
+}
+
+// Steps remembered for back and rewind
+const HISTORY_LIMIT: usize = 1 << 20;
+
+struct Debugger {
+    program: Program,
+    history: History,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, i64>
+}
+
+impl Debugger {
+    fn new(program: Program) -> Debugger {
+        let history = History::with_limit(&program, HISTORY_LIMIT);
+        Debugger { program, history, breakpoints: BTreeSet::new(), watchpoints: BTreeMap::new() }
+    }
+
+    // After the machine jumps in time, watchpoints compare against the values it now has
+    fn reset_watchpoints(&mut self) {
+        for (address, last) in self.watchpoints.iter_mut() {
+            *last = self.program.peek(*address);
+        }
+    }
+
+    fn single_step(&mut self, log: &mut Vec<String>) -> Option<Stop> {
+        if self.program.is_terminated() {
+            return Some(Stop::Halted)
+        }
+        match self.program.step_observed(&mut self.history) {
+            Err(Error::NoInput { .. }) => return Some(Stop::AwaitingInput),
+            Err(e) => return Some(Stop::Crashed(e)),
+            Ok(State::Output(x)) | Ok(State::OutputAwaitingInput(x)) => log.push(format!("output: {}", x)),
+            Ok(_) => ()
+        }
+        for (address, last) in self.watchpoints.iter_mut() {
+            let value = self.program.peek(*address);
+            if value != *last {
+                let stop = Stop::Watchpoint { address: *address, old: *last, new: value };
+                *last = value;
+                return Some(stop)
+            }
+        }
+        None
+    }
+
+    fn run(&mut self, steps: Option<usize>, log: &mut Vec<String>) {
+        let mut count = 0;
+        let stop = loop {
+            if let Some(stop) = self.single_step(log) {
+                break Some(stop)
+            }
+            count += 1;
+            if steps.map(|n| count >= n).unwrap_or(false) {
+                break None
+            }
+            let ip = self.program.instruction_pointer();
+            if steps.is_none() && self.breakpoints.contains(&ip) {
+                break Some(Stop::Breakpoint(ip))
+            }
+        };
+        match stop {
+            None => (),
+            Some(Stop::Breakpoint(address)) => log.push(format!("breakpoint at {}", address)),
+            Some(Stop::Watchpoint { address, old, new }) => log.push(format!("watchpoint [{}]: {} -> {}", address, old, new)),
+            Some(Stop::AwaitingInput) => log.push("waiting for input".to_string()),
+            Some(Stop::Halted) => log.push("program halted".to_string()),
+            Some(Stop::Crashed(e)) => log.push(format!("program crashed: {}", e))
+        }
+        log.push(self.program.decode(self.program.instruction_pointer()).to_string());
+    }
+
+    fn registers(&self) -> String {
+        let input: Vec<String> = self.program.input_buffer().iter().map(|x| x.to_string()).collect();
+        format!("ip={} rb={} input=[{}]", self.program.instruction_pointer(), self.program.relative_base(), input.join(", "))
+    }
+
+    // Returns None when the session should end
+    fn execute(&mut self, line: &str) -> Option<Vec<String>> {
+        let mut log = vec!();
+        let (command, rest) = match line.trim().find(char::is_whitespace) {
+            Some(idx) => (&line.trim()[..idx], line.trim()[idx..].trim()),
+            None => (line.trim(), "")
+        };
+        let args: Vec<Option<i64>> = rest.split_whitespace().map(|arg| arg.parse().ok()).collect();
+        let address = |k: usize| args.get(k).copied().flatten().filter(|&a| a >= 0).map(|a| a as usize);
+        match command {
+            "" => (),
+            "help" | "h" => log.push(HELP.to_string()),
+            "quit" | "q" => return None,
+            "step" | "s" => self.run(Some(address(0).unwrap_or(1)), &mut log),
+            "continue" | "c" => self.run(None, &mut log),
+            "break" | "b" => match address(0) {
+                Some(a) => {
+                    self.breakpoints.insert(a);
+                    log.push(format!("breakpoint at {}", a));
+                },
+                None => log.push("usage: break <addr>".to_string())
+            },
+            "watch" | "w" => match address(0) {
+                Some(a) => {
+                    self.watchpoints.insert(a, self.program.peek(a));
+                    log.push(format!("watching [{}] = {}", a, self.program.peek(a)));
+                },
+                None => log.push("usage: watch <addr>".to_string())
+            },
+            "delete" | "d" => match address(0) {
+                Some(a) if self.breakpoints.remove(&a) | self.watchpoints.remove(&a).is_some() => (),
+                Some(a) => log.push(format!("nothing set at {}", a)),
+                None => log.push("usage: delete <addr>".to_string())
+            },
+            "info" | "i" => {
+                for b in &self.breakpoints {
+                    log.push(format!("breakpoint at {}", b));
+                }
+                for w in self.watchpoints.keys() {
+                    log.push(format!("watchpoint on [{}]", w));
+                }
+            },
+            "regs" | "r" => log.push(self.registers()),
+            "mem" | "x" => match address(0) {
+                Some(start) => {
+                    let count = address(1).unwrap_or(8);
+                    let words: Vec<String> = (start..start + count).map(|a| self.program.peek(a).to_string()).collect();
+                    log.push(format!("{:>5}: {}", start, words.join(" ")));
+                },
+                None => log.push("usage: mem <addr> [n]".to_string())
+            },
+            "list" | "l" => {
+                let mut a = address(0).unwrap_or_else(|| self.program.instruction_pointer());
+                for _ in 0..address(1).unwrap_or(5) {
+                    let line = self.program.decode(a);
+                    a += line.width();
+                    log.push(line.to_string());
+                }
+            },
+            "back" => {
+                let mut count = 0;
+                while count < address(0).unwrap_or(1) && self.history.step_back(&mut self.program) {
+                    count += 1;
+                }
+                self.reset_watchpoints();
+                log.push(format!("stepped back {} (step {})", count, self.history.steps()));
+                log.push(self.program.decode(self.program.instruction_pointer()).to_string());
+            },
+            "rewind" => match address(0) {
+                Some(a) => match self.history.run_back_to(&mut self.program, a) {
+                    Some(count) => {
+                        self.reset_watchpoints();
+                        log.push(format!("stepped back {} (step {})", count, self.history.steps()));
+                        log.push(self.program.decode(self.program.instruction_pointer()).to_string());
+                    },
+                    None => log.push(format!("no record of executing {}", a))
+                },
+                None => log.push("usage: rewind <addr>".to_string())
+            },
+            "writer" => match address(0) {
+                Some(a) => match self.history.last_writer(a) {
+                    Some(w) => log.push(format!("[{}] written at step {} by instruction at {}: {} -> {}",
+                        a, w.step, w.instruction, w.write.old, w.write.new)),
+                    None => log.push(format!("no recorded write to [{}]", a))
+                },
+                None => log.push("usage: writer <addr>".to_string())
+            },
+            "input" => {
+                if args.is_empty() || args.iter().any(|arg| arg.is_none()) {
+                    log.push("usage: input <v> [v...]".to_string());
+                } else {
+                    for x in args.iter().flatten() {
+                        self.program.read_input(*x);
+                    }
+                    log.push(self.registers());
+                }
+            },
+            "ascii" => {
+                for c in rest.chars().chain(Some('\n')) {
+                    self.program.read_input(c as i64);
+                }
+                log.push(self.registers());
+            },
+            "save" if !rest.is_empty() => match self.program.save_snapshot(rest) {
+                Ok(()) => log.push(format!("saved to {}", rest)),
+                Err(e) => log.push(format!("cannot save to {}: {}", rest, e))
+            },
+            "load" if !rest.is_empty() => match Program::load_snapshot(rest) {
+                Ok(program) => {
+                    self.history = History::with_limit(&program, HISTORY_LIMIT);
+                    self.program = program;
+                    self.reset_watchpoints();
+                    log.push(self.registers());
+                },
+                Err(e) => log.push(format!("cannot load {}: {}", rest, e))
+            },
+            "save" | "load" => log.push(format!("usage: {} <file>", command)),
+            other => log.push(format!("unknown command {}; try help", other))
+        }
+        Some(log)
+    }
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: debugger <program file>");
+            process::exit(1);
+        }
+    };
+    let program = match fs::read_to_string(&path) {
+        Ok(source) => Program::from_str(source.trim()),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut debugger = Debugger::new(program);
+    println!("{}", debugger.program.decode(0));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush().ok();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break
+        };
+        match debugger.execute(&line) {
+            None => break,
+            Some(log) => for entry in log {
+                println!("{}", entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger() -> Debugger {
+        // 0: IN [13]; 2: ADD [13], #1, [13]; 6: OUT [13]; 8: JT #1, #0; 11: HLT
+        Debugger::new(Program::from_str("3,13,1001,13,1,13,4,13,1105,1,0,99,0,0"))
+    }
+
+    #[test]
+    fn step_test() {
+        let mut debugger = debugger();
+        let log = debugger.execute("step").unwrap();
+        assert_eq!(log[0], "waiting for input");
+
+        debugger.execute("input 41").unwrap();
+        assert_eq!(debugger.execute("regs").unwrap(), ["ip=0 rb=0 input=[41]"]);
+        let log = debugger.execute("s 3").unwrap();
+        assert_eq!(log[0], "output: 42");
+        assert_eq!(debugger.program.instruction_pointer(), 8);
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let mut debugger = debugger();
+        debugger.execute("b 6").unwrap();
+        debugger.execute("input 1 2").unwrap();
+        let log = debugger.execute("c").unwrap();
+        assert_eq!(log[0], "breakpoint at 6");
+        let log = debugger.execute("c").unwrap();
+        assert_eq!(log[..2], ["output: 2".to_string(), "breakpoint at 6".to_string()]);
+        debugger.execute("d 6").unwrap();
+        let log = debugger.execute("c").unwrap();
+        assert_eq!(log[..2], ["output: 3".to_string(), "waiting for input".to_string()]);
+    }
+
+    #[test]
+    fn watchpoint_test() {
+        let mut debugger = debugger();
+        debugger.execute("w 13").unwrap();
+        debugger.execute("ascii A").unwrap();
+        let log = debugger.execute("c").unwrap();
+        assert_eq!(log[0], "watchpoint [13]: 0 -> 65");
+        let log = debugger.execute("c").unwrap();
+        assert_eq!(log[0], "watchpoint [13]: 65 -> 66");
+        assert_eq!(debugger.execute("x 12 2").unwrap(), ["   12: 0 66"]);
+    }
+
+    #[test]
+    fn history_test() {
+        let mut debugger = debugger();
+        debugger.execute("input 1 2").unwrap();
+        debugger.execute("b 6").unwrap();
+        debugger.execute("c").unwrap();
+        debugger.execute("c").unwrap();
+        assert_eq!(debugger.execute("writer 13").unwrap(), ["[13] written at step 5 by instruction at 2: 2 -> 3"]);
+
+        let log = debugger.execute("back 2").unwrap();
+        assert_eq!(log[0], "stepped back 2 (step 4)");
+        assert_eq!(debugger.program.peek(13), 2);
+        let log = debugger.execute("rewind 6").unwrap();
+        assert_eq!(log[0], "stepped back 2 (step 2)");
+        assert_eq!(debugger.execute("regs").unwrap(), ["ip=6 rb=0 input=[2]"]);
+        assert_eq!(debugger.execute("rewind 11").unwrap(), ["no record of executing 11"]);
+        assert_eq!(debugger.execute("back 10").unwrap()[0], "stepped back 2 (step 0)");
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let path = std::env::temp_dir().join(format!("icdb-snapshot-test-{}", process::id()));
+        let path = path.to_str().unwrap();
+        let mut debugger = debugger();
+        debugger.execute("input 9").unwrap();
+        debugger.execute("s 2").unwrap();
+        debugger.execute(&format!("save {}", path)).unwrap();
+
+        let mut resumed = Debugger::new(Program::from_str("99"));
+        assert_eq!(resumed.execute(&format!("load {}", path)).unwrap(), ["ip=6 rb=0 input=[]"]);
+        fs::remove_file(path).unwrap();
+        assert_eq!(resumed.execute("s").unwrap()[0], "output: 10");
+    }
+
+    #[test]
+    fn crash_test() {
+        let mut debugger = Debugger::new(Program::from_str("1,0,0,0,42"));
+        let log = debugger.execute("c").unwrap();
+        assert_eq!(log[0], "program crashed: unknown opcode (instruction 42 at 4)");
+        assert!(debugger.execute("q").is_none());
+    }
+}
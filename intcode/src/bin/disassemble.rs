@@ -0,0 +1,12 @@
+use std::io;
+use std::io::prelude::*;
+
+use intcode::Program;
+
+fn main() {
+    let stdin = io::stdin();
+    match stdin.lock().lines().next().and_then(|line| line.ok()) {
+        Some(line) => println!("{}", Program::from_str(&line).disassemble()),
+        None => eprintln!("Error reading program from stdin!")
+    }
+}
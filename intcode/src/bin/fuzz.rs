@@ -0,0 +1,40 @@
+// Fuzzes the interpreter with random programs, printing each panic found as a minimized case.
+//
+//   cargo run --bin fuzz -- [cases] [seed]
+//
+// Build without --release, so that arithmetic overflow panics too. Each case printed can be added
+// to the regression list in src/fuzz.rs once fixed.
+
+use std::collections::HashSet;
+use std::env;
+use std::panic;
+use std::process;
+
+use intcode::fuzz::{Case, Rng};
+
+fn main() {
+    let args: Vec<u64> = match env::args().skip(1).map(|s| s.parse()).collect() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("usage: fuzz [cases] [seed]: {}", e);
+            process::exit(1);
+        }
+    };
+    let cases = args.first().copied().unwrap_or(100_000);
+    let seed = args.get(1).copied().unwrap_or(2019);
+
+    // the panics are reported below, minimized
+    panic::set_hook(Box::new(|_| ()));
+    let mut rng = Rng::new(seed);
+    let mut found = HashSet::new();
+    for _ in 0..cases {
+        let case = Case::generate(&mut rng);
+        if let Err(message) = case.run() {
+            let small = case.minimize(|c| c.run().as_ref().err() == Some(&message));
+            if found.insert(small.to_string()) {
+                println!("{}  ({})", small, message);
+            }
+        }
+    }
+    println!("{} cases, {} distinct panics", cases, found.len());
+}
@@ -0,0 +1,58 @@
+// Profiles a run of an intcode program, printing a report of where its steps went.
+//
+//   cargo run --release --bin profile -- <program file> [--folded <file>] [input ...]
+//
+// The inputs are queued before the run. With --folded, the call stacks are also written in the
+// folded format, ready for flamegraph.pl or inferno-flamegraph.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::profile::Profiler;
+use intcode::Program;
+
+const TOP: usize = 20;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, rest) = match args.split_first() {
+        Some((path, rest)) => (path, rest),
+        None => {
+            eprintln!("usage: profile <program file> [--folded <file>] [input ...]");
+            process::exit(1);
+        }
+    };
+    let (folded, inputs) = match rest {
+        [flag, file, inputs @ ..] if flag == "--folded" => (Some(file), inputs),
+        inputs => (None, inputs)
+    };
+    let inputs: Vec<i64> = match inputs.iter().map(|s| s.parse()).collect() {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("Inputs must be integers: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut program = match fs::read_to_string(path) {
+        Ok(source) => Program::from_str(source.trim()),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut profiler = Profiler::new(&program);
+    match program.run_observed(&inputs, |_| (), &mut profiler) {
+        Ok(result) => println!("Program finished with output {:?}", result),
+        Err(e) => println!("Program stopped: {}", e)
+    }
+    println!("{}", profiler.report(TOP));
+
+    if let Some(file) = folded {
+        if let Err(e) = fs::write(file, profiler.folded()) {
+            eprintln!("Error writing {}: {}", file, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Records an interactive run of an intcode program, or replays a recording against it.
+//
+//   cargo run --bin session -- record <program file> <session file>
+//   cargo run --bin session -- replay <program file> <session file>
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+use intcode::session::{Recorder, Session};
+use intcode::{Program, State};
+
+const USAGE: &str = "\
+usage: session record <program file> <session file>
+       session replay <program file> <session file>
+
+record runs the program interactively: each line of stdin is sent as integers if it is a list of
+them, or else as ASCII text followed by a newline. replay checks the program against a recording.";
+
+fn read_program(path: &str) -> Program {
+    match fs::read_to_string(path) {
+        Ok(source) => Program::from_str(source.trim()),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Vec<i64> {
+    let words: Option<Vec<i64>> = line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|w| !w.is_empty()).map(|w| w.parse().ok()).collect();
+    match words {
+        Some(words) if !words.is_empty() => words,
+        _ => line.chars().chain(Some('\n')).map(|c| c as i64).collect()
+    }
+}
+
+fn record(mut program: Program) -> Session {
+    let mut recorder = Recorder::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        match program.await_output_observed(&mut recorder) {
+            Ok(State::Output(x)) | Ok(State::OutputAwaitingInput(x)) => println!("{}", x),
+            Ok(State::AwaitingInput) => match lines.next() {
+                Some(Ok(line)) => for x in parse_line(&line) {
+                    program.read_input(x);
+                },
+                _ => break
+            },
+            Ok(State::Running) => continue,
+            Ok(_) => break,
+            Err(e) => {
+                eprintln!("Program crashed: {}", e);
+                break
+            }
+        }
+    }
+    recorder.finish()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(|s| s.as_str()).collect::<Vec<&str>>()[..] {
+        ["record", program, path] => {
+            let session = record(read_program(program));
+            if let Err(e) = session.save(path) {
+                eprintln!("Error writing {}: {}", path, e);
+                process::exit(1);
+            }
+            eprintln!("Recorded {} steps", session.steps);
+        },
+        ["replay", program, path] => {
+            let session = match Session::load(path) {
+                Ok(session) => session,
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", path, e);
+                    process::exit(1);
+                }
+            };
+            match session.replay(&mut read_program(program)) {
+                Ok(()) => println!("Replayed {} steps and {} outputs", session.steps, session.outputs().len()),
+                Err(divergence) => {
+                    println!("Replay diverged: {}", divergence);
+                    process::exit(1);
+                }
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::env;
+use std::io;
+use std::io::prelude::*;
+
+use intcode::Program;
+
+// Reads a program from stdin and prints it as Rust source; with --lib, leaves out the main function
+fn main() {
+    let main = !env::args().skip(1).any(|arg| arg == "--lib");
+    let stdin = io::stdin();
+    match stdin.lock().lines().next().and_then(|line| line.ok()) {
+        Some(line) => print!("{}", Program::from_str(&line).to_rust(main)),
+        None => eprintln!("Error reading program from stdin!")
+    }
+}
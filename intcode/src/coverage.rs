@@ -0,0 +1,205 @@
+// Code coverage. Attach one Coverage as the observer of every run of a program (it can be shared
+// across runs, or merged) to count executions of each instruction and which ways each JT/JF went.
+// The listing is the disassembly annotated with hit counts, in the manner of gcov:
+//
+//       3:     0: IN [12]
+//   #####:     5: OUT #1
+//       3:     2: JF [12], [15]    ; taken 2, not taken 1
+//
+// Instructions that static analysis finds reachable but that never ran are marked #####; lines it
+// does not reach are left unmarked. Conditional jumps whose test is immediate always go the same
+// way, and are not counted as branches.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::analysis::Analysis;
+use crate::dialect::Dialect;
+use crate::disasm::{self, Item};
+use crate::memory::Memory;
+use crate::trace::{Event, Observer};
+use crate::{Instruction, ParameterMode, Program};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Summary {
+    pub instructions: usize,
+    pub executed: usize,
+    // Two directions for each conditional jump
+    pub branches: usize,
+    pub branches_followed: usize
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |n: usize, of: usize| if of == 0 { 100.0 } else { 100.0 * n as f64 / of as f64 };
+        write!(writer, "instructions {}/{} ({:.1}%), branches {}/{} ({:.1}%)",
+            self.executed, self.instructions, percent(self.executed, self.instructions),
+            self.branches_followed, self.branches, percent(self.branches_followed, self.branches))
+    }
+}
+
+#[derive(Clone)]
+pub struct Coverage {
+    memory: Vec<i64>,
+    dialect: Option<Arc<Dialect>>,
+    // Addresses of the reachable instructions
+    reachable: Vec<usize>,
+    hits: HashMap<usize, u64>,
+    branches: HashMap<usize, Branch>
+}
+
+fn is_branch(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::JumpIfTrue { m1, .. } | Instruction::JumpIfFalse { m1, .. } => *m1 != ParameterMode::Immediate,
+        _ => false
+    }
+}
+
+impl Coverage {
+    pub fn new<M: Memory>(program: &Program<M>) -> Coverage {
+        Coverage::with_analysis(program, &program.analyse())
+    }
+
+    pub fn with_analysis<M: Memory>(program: &Program<M>, analysis: &Analysis) -> Coverage {
+        let mut reachable: Vec<usize> = analysis.blocks.values()
+            .flat_map(|block| block.lines.iter().map(|line| line.address))
+            .collect();
+        reachable.sort_unstable();
+        Coverage {
+            memory: program.image(),
+            dialect: program.dialect.clone(),
+            reachable,
+            hits: HashMap::new(),
+            branches: HashMap::new()
+        }
+    }
+
+    pub fn hits(&self, address: usize) -> u64 {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, address: usize) -> Option<Branch> {
+        self.branches.get(&address).copied()
+    }
+
+    // Adds the counts from coverage of another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        for (address, n) in &other.hits {
+            *self.hits.entry(*address).or_insert(0) += n;
+        }
+        for (address, branch) in &other.branches {
+            let ours = self.branches.entry(*address).or_default();
+            ours.taken += branch.taken;
+            ours.not_taken += branch.not_taken;
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary { instructions: 0, executed: 0, branches: 0, branches_followed: 0 };
+        for &address in &self.reachable {
+            summary.instructions += 1;
+            if self.hits(address) > 0 {
+                summary.executed += 1;
+            }
+            if let Item::Instruction(instruction, _) = disasm::decode_in(&self.memory, address, self.dialect.as_deref()).item {
+                if is_branch(&instruction) {
+                    let branch = self.branch(address).unwrap_or_default();
+                    summary.branches += 2;
+                    summary.branches_followed += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+                }
+            }
+        }
+        summary
+    }
+
+    // Addresses of reachable instructions that never ran, and of branches that only went one way
+    pub fn gaps(&self) -> (Vec<usize>, Vec<usize>) {
+        let missed = self.reachable.iter().copied().filter(|&address| self.hits(address) == 0).collect();
+        let one_way = self.branches.iter()
+            .filter(|(_, branch)| branch.taken == 0 || branch.not_taken == 0)
+            .map(|(address, _)| *address);
+        let mut one_way: Vec<usize> = one_way.collect();
+        one_way.sort_unstable();
+        (missed, one_way)
+    }
+
+    pub fn listing(&self) -> String {
+        let mut listing: Vec<String> = disasm::disassemble_in(&self.memory, self.dialect.as_deref()).iter().map(|line| {
+            let count = match self.hits(line.address) {
+                0 if self.reachable.binary_search(&line.address).is_ok() => "#####".to_string(),
+                0 => String::new(),
+                n => n.to_string()
+            };
+            let mut text = format!("{:>8}: {:>5}: {}", count, line.address, line.text());
+            if let Some(branch) = self.branch(line.address) {
+                text = format!("{:<40}; taken {}, not taken {}", text, branch.taken, branch.not_taken);
+            }
+            text
+        }).collect();
+        listing.push(self.summary().to_string());
+        listing.join("\n")
+    }
+}
+
+impl Observer for Coverage {
+    fn on_step(&mut self, event: &Event) {
+        *self.hits.entry(event.address).or_insert(0) += 1;
+        if is_branch(&event.instruction) {
+            let jump_if_true = matches!(event.instruction, Instruction::JumpIfTrue { .. });
+            let branch = self.branches.entry(event.address).or_default();
+            match event.operands[0] {
+                Some(test) if (test != 0) == jump_if_true => branch.taken += 1,
+                _ => branch.not_taken += 1
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from day05_tests: outputs 0 if the input was 0, else 1
+    const JUMP: &str = "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9";
+
+    fn cover(runs: &[i64]) -> Coverage {
+        let program = Program::from_str(JUMP);
+        let mut coverage = Coverage::new(&program);
+        for &input in runs {
+            program.clone().run_observed(&[input], |_| (), &mut coverage).unwrap();
+        }
+        coverage
+    }
+
+    #[test]
+    fn branch_test() {
+        let coverage = cover(&[0, 0]);
+        assert_eq!(coverage.hits(2), 2);
+        assert_eq!(coverage.branch(2), Some(Branch { taken: 2, not_taken: 0 }));
+        assert_eq!(coverage.summary(), Summary { instructions: 5, executed: 4, branches: 2, branches_followed: 1 });
+        assert_eq!(coverage.gaps(), (vec!(5), vec!(2)));
+
+        let mut merged = cover(&[7]);
+        merged.merge(&coverage);
+        assert_eq!(merged.branch(2), Some(Branch { taken: 2, not_taken: 1 }));
+        assert_eq!(merged.summary().to_string(), "instructions 5/5 (100.0%), branches 2/2 (100.0%)");
+    }
+
+    #[test]
+    fn listing_test() {
+        let listing = cover(&[0]).listing();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "       1:     0: IN [12]");
+        assert_eq!(lines[1], "       1:     2: JF [12], [15]          ; taken 1, not taken 0");
+        assert_eq!(lines[2], "   #####:     5: ADD [13], [14], [13]");
+        assert_eq!(lines[5], "        :    12: DB -1");
+        assert_eq!(lines.last(), Some(&"instructions 4/5 (80.0%), branches 1/2 (50.0%)"));
+    }
+}
@@ -0,0 +1,138 @@
+// Loop detection. With it switched on, every step of `await_output` (and so `run`) updates a
+// hash of the machine's memory, instruction pointer and relative base, and Brent's algorithm
+// watches the sequence of hashes for a repeat. A repeated state means the machine will go round
+// the same cycle forever, so the run stops with State::InfiniteLoop and the cycle's length.
+//
+// The memory hash is kept up to date from the words each step writes, so a step costs the same
+// however large memory is. Input and output both count as progress: reading input starts the
+// watch afresh, as the next input may break the cycle, and so does writing output, so a program
+// that outputs forever is never reported. States are compared by hash alone; a false alarm would
+// take a 64-bit collision.
+
+use crate::memory::Memory;
+use crate::trace::Event;
+
+// splitmix64's finalizer
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Zero words count for nothing, so memory's length does not matter
+fn word_hash(address: usize, word: i64) -> u64 {
+    if word == 0 { 0 } else { mix(mix(address as u64) ^ word as u64) }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct LoopDetector {
+    // The sum of the hashes of the words in memory
+    memory: u64,
+    relative_base: i64,
+    // The state Brent's algorithm compares against, the steps since it was taken, and the number
+    // of steps after which it is replaced
+    tortoise: u64,
+    length: u64,
+    power: u64
+}
+
+impl LoopDetector {
+    pub(crate) fn new<M: Memory>(memory: &M, instruction_pointer: usize, relative_base: i64) -> LoopDetector {
+        let memory = memory.nonzero_words().into_iter()
+            .fold(0u64, |sum, (address, word)| sum.wrapping_add(word_hash(address, word)));
+        let mut detector = LoopDetector { memory, relative_base, tortoise: 0, length: 0, power: 0 };
+        detector.restart(instruction_pointer);
+        detector
+    }
+
+    fn state(&self, instruction_pointer: usize) -> u64 {
+        mix(self.memory ^ mix(instruction_pointer as u64) ^ mix(self.relative_base as u64).rotate_left(32))
+    }
+
+    fn restart(&mut self, instruction_pointer: usize) {
+        self.tortoise = self.state(instruction_pointer);
+        self.length = 1;
+        self.power = 1;
+    }
+
+    // Takes in a step; the length of the cycle, if the state it left was seen before
+    pub(crate) fn observe(&mut self, event: &Event) -> Option<u64> {
+        if let Some(write) = event.write {
+            self.memory = self.memory
+                .wrapping_sub(word_hash(write.address, write.old))
+                .wrapping_add(word_hash(write.address, write.new));
+        }
+        if let Some((_, new)) = event.relative_base {
+            self.relative_base = new;
+        }
+        if event.input.is_some() || event.output.is_some() {
+            self.restart(event.next);
+            return None
+        }
+        let hare = self.state(event.next);
+        if hare == self.tortoise {
+            let length = self.length;
+            self.restart(event.next);
+            return Some(length)
+        }
+        if self.power == self.length {
+            self.tortoise = hare;
+            self.power *= 2;
+            self.length = 0;
+        }
+        self.length += 1;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Program, State};
+
+    #[test]
+    fn spin_test() {
+        let mut program = Program::from_str("1105,1,0");
+        program.set_loop_detection(true);
+        assert_eq!(program.await_output(), Ok(State::InfiniteLoop(1)));
+
+        // negates x at 9 forever: the state repeats every two trips round the loop
+        let mut program = Program::from_str("1002,9,-1,9,1105,1,0,99,99,5");
+        program.set_loop_detection(true);
+        assert_eq!(program.await_output(), Ok(State::InfiniteLoop(4)));
+        assert_eq!(program.run(&[], |_| ()), Err(Error::InfiniteLoop { instruction_pointer: 0, opcode: 1002, length: 4 }));
+    }
+
+    #[test]
+    fn progress_test() {
+        // counts down from the input, outputting each value
+        let countdown = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+        let mut program = Program::from_str(countdown);
+        program.set_loop_detection(true);
+        let mut outputs = vec!();
+        assert_eq!(program.run(&[1000], |x| outputs.push(x)), Ok(Some(1)));
+        assert_eq!(outputs.len(), 1000);
+
+        // echoes its input forever, which is no loop while input keeps coming
+        let mut program = Program::from_str("3,5,4,5,1105,1,0");
+        program.set_loop_detection(true);
+        assert_eq!(program.run(&[1, 1, 1], |_| ()).map_err(|e| e.to_string()), Err("input requested but none is queued (instruction 3 at 0)".to_string()));
+    }
+
+    #[test]
+    fn output_test() {
+        // output forever, straight away or after a jump; every call outputs, none reports a loop
+        for source in &["104,1,1105,1,0", "1105,1,3,104,1,1105,1,0"] {
+            let mut program = Program::from_str(source);
+            program.set_loop_detection(true);
+            for _ in 0..20 {
+                assert_eq!(program.await_output(), Ok(State::Output(1)), "{}", source);
+            }
+        }
+
+        // outputs once, then spins
+        let mut program = Program::from_str("104,1,1105,1,2");
+        program.set_loop_detection(true);
+        assert_eq!(program.await_output(), Ok(State::Output(1)));
+        assert_eq!(program.await_output(), Ok(State::InfiniteLoop(1)));
+    }
+}
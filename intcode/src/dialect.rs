@@ -0,0 +1,387 @@
+// Dialects extend the instruction set with custom opcodes and parameter modes, for VM variants that
+// should not need a fork of the interpreter:
+//
+//   let mut dialect = Dialect::new();
+//   dialect.define_opcode(10, Assert);
+//   dialect.define_mode(3, Indirect);
+//   program.set_dialect(dialect);
+//
+// The standard opcodes and modes always take precedence, so an extension uses an opcode (below 100)
+// or mode digit that the standard set leaves free. An extension instruction's effects - its write,
+// the input it takes, its output and its jump - are applied together once it returns, so one that
+// fails leaves the machine unchanged, like any other failed step. Snapshots do not record the
+// dialect; set it again on a restored machine.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::memory::Memory;
+use crate::{Error, ParameterMode, Program};
+
+// The machine state an extension can look at
+pub trait Machine {
+    fn instruction_pointer(&self) -> usize;
+    fn relative_base(&self) -> i64;
+    fn peek(&self, address: usize) -> i64;
+}
+
+// The parameters of the instruction being executed, and its effects. Each of write, input and
+// output may happen at most once per instruction.
+pub trait Operands: Machine {
+    // The value of parameter `n`, counting from 0, according to its mode
+    fn read(&mut self, n: usize) -> Result<i64, Error>;
+    fn write(&mut self, n: usize, value: i64) -> Result<(), Error>;
+    // Takes the next queued input; fails with NoInput if there is none
+    fn input(&mut self) -> Result<i64, Error>;
+    fn output(&mut self, value: i64) -> Result<(), Error>;
+    // Continues at `target` instead of the next instruction
+    fn jump(&mut self, target: i64) -> Result<(), Error>;
+    // An Error::Extension for the current instruction
+    fn fail(&self, message: &str) -> Error;
+}
+
+pub trait Opcode: Send + Sync {
+    fn mnemonic(&self) -> &'static str;
+    // At most 3
+    fn arity(&self) -> usize;
+    fn execute(&self, operands: &mut dyn Operands) -> Result<(), Error>;
+}
+
+pub trait Mode: Send + Sync {
+    // The address a parameter word refers to
+    fn address(&self, word: i64, machine: &dyn Machine) -> i64;
+}
+
+#[derive(Clone, Default)]
+pub struct Dialect {
+    opcodes: HashMap<i64, Arc<dyn Opcode>>,
+    modes: HashMap<i64, Arc<dyn Mode>>
+}
+
+impl Dialect {
+    pub fn new() -> Dialect {
+        Dialect::default()
+    }
+
+    pub fn define_opcode<O: Opcode + 'static>(&mut self, opcode: i64, definition: O) {
+        assert!((10..99).contains(&opcode), "extension opcodes must be from 10 to 98");
+        assert!(definition.arity() <= 3, "extension instructions take at most 3 parameters");
+        self.opcodes.insert(opcode, Arc::new(definition));
+    }
+
+    pub fn define_mode<D: Mode + 'static>(&mut self, digit: i64, mode: D) {
+        assert!((3..10).contains(&digit), "extension parameter modes must be from 3 to 9");
+        self.modes.insert(digit, Arc::new(mode));
+    }
+
+    pub fn opcode(&self, opcode: i64) -> Option<&dyn Opcode> {
+        self.opcodes.get(&opcode).map(|definition| definition.as_ref())
+    }
+
+    pub fn mode(&self, digit: i64) -> Option<&dyn Mode> {
+        self.modes.get(&digit).map(|mode| mode.as_ref())
+    }
+}
+
+impl fmt::Debug for Dialect {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        let mut opcodes: Vec<(i64, &str)> = self.opcodes.iter().map(|(opcode, d)| (*opcode, d.mnemonic())).collect();
+        opcodes.sort_unstable();
+        let mut modes: Vec<&i64> = self.modes.keys().collect();
+        modes.sort_unstable();
+        write!(writer, "Dialect {{ opcodes: {:?}, modes: {:?} }}", opcodes, modes)
+    }
+}
+
+impl<M: Memory> Machine for Program<M> {
+    fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    fn peek(&self, address: usize) -> i64 {
+        self.memory.read(address)
+    }
+}
+
+// What an extension instruction did, to be applied to the machine once it has succeeded
+pub(crate) struct Effects {
+    pub operands: [Option<i64>; 3],
+    pub write: Option<(usize, i64)>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+    pub jump: Option<usize>
+}
+
+pub(crate) struct Context<'a, M: Memory> {
+    program: &'a Program<M>,
+    modes: [ParameterMode; 3],
+    arity: usize,
+    effects: Effects
+}
+
+impl<'a, M: Memory> Context<'a, M> {
+    pub(crate) fn new(program: &'a Program<M>, modes: [ParameterMode; 3], arity: usize) -> Context<'a, M> {
+        let effects = Effects { operands: [None; 3], write: None, input: None, output: None, jump: None };
+        Context { program, modes, arity, effects }
+    }
+
+    pub(crate) fn finish(self) -> Effects {
+        self.effects
+    }
+
+    fn parameter(&self, n: usize) -> Result<usize, Error> {
+        if n < self.arity {
+            Ok(self.program.instruction_pointer + 1 + n)
+        } else {
+            Err(self.fail(&format!("no parameter {}", n)))
+        }
+    }
+}
+
+impl<'a, M: Memory> Machine for Context<'a, M> {
+    fn instruction_pointer(&self) -> usize {
+        self.program.instruction_pointer
+    }
+
+    fn relative_base(&self) -> i64 {
+        self.program.relative_base
+    }
+
+    fn peek(&self, address: usize) -> i64 {
+        self.program.peek(address)
+    }
+}
+
+impl<'a, M: Memory> Operands for Context<'a, M> {
+    fn read(&mut self, n: usize) -> Result<i64, Error> {
+        let value = self.program.get(self.parameter(n)?, &self.modes[n])?;
+        self.effects.operands[n] = Some(value);
+        Ok(value)
+    }
+
+    fn write(&mut self, n: usize, value: i64) -> Result<(), Error> {
+        let parameter = self.parameter(n)?;
+        if self.effects.write.is_some() {
+            return Err(self.fail("more than one write"))
+        }
+        if let ParameterMode::Immediate = self.modes[n] {
+            return Err(Error::ImmediateWrite {
+                instruction_pointer: self.program.instruction_pointer,
+                opcode: self.program.peek(self.program.instruction_pointer)
+            })
+        }
+        let address = self.program.address(parameter, &self.modes[n])?;
+        self.effects.operands[n] = Some(address as i64);
+        self.effects.write = Some((address, value));
+        Ok(())
+    }
+
+    fn input(&mut self) -> Result<i64, Error> {
+        if self.effects.input.is_some() {
+            return Err(self.fail("more than one input"))
+        }
+        match self.program.input_buffer.front() {
+            Some(&input) => {
+                self.effects.input = Some(input);
+                Ok(input)
+            },
+            None => Err(self.program.no_input())
+        }
+    }
+
+    fn output(&mut self, value: i64) -> Result<(), Error> {
+        if self.effects.output.is_some() {
+            return Err(self.fail("more than one output"))
+        }
+        self.effects.output = Some(value);
+        Ok(())
+    }
+
+    fn jump(&mut self, target: i64) -> Result<(), Error> {
+        let target = usize::try_from(target).map_err(|_| Error::InvalidJumpTarget {
+            instruction_pointer: self.program.instruction_pointer,
+            opcode: self.program.peek(self.program.instruction_pointer),
+            target
+        })?;
+        self.effects.jump = Some(target);
+        Ok(())
+    }
+
+    fn fail(&self, message: &str) -> Error {
+        Error::Extension {
+            instruction_pointer: self.program.instruction_pointer,
+            opcode: self.program.peek(self.program.instruction_pointer),
+            message: message.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::trace::Event;
+    use crate::{Instruction, State};
+
+    // ASSERT a, b: fails unless a == b
+    struct Assert;
+
+    impl Opcode for Assert {
+        fn mnemonic(&self) -> &'static str {
+            "ASSERT"
+        }
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn execute(&self, operands: &mut dyn Operands) -> Result<(), Error> {
+            let (a, b) = (operands.read(0)?, operands.read(1)?);
+            if a == b {
+                Ok(())
+            } else {
+                Err(operands.fail(&format!("assertion failed: {} != {}", a, b)))
+            }
+        }
+    }
+
+    // PRN a: hands a to the host without it becoming output
+    struct Print(Arc<Mutex<Vec<i64>>>);
+
+    impl Opcode for Print {
+        fn mnemonic(&self) -> &'static str {
+            "PRN"
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn execute(&self, operands: &mut dyn Operands) -> Result<(), Error> {
+            let x = operands.read(0)?;
+            self.0.lock().unwrap().push(x);
+            Ok(())
+        }
+    }
+
+    // INC a, b: a + 1 into b, then jumps back to 0 if that was not 0
+    struct IncrementAndLoop;
+
+    impl Opcode for IncrementAndLoop {
+        fn mnemonic(&self) -> &'static str {
+            "INC"
+        }
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn execute(&self, operands: &mut dyn Operands) -> Result<(), Error> {
+            let x = operands.read(0)? + 1;
+            operands.write(1, x)?;
+            if x != 0 {
+                operands.jump(0)?;
+            }
+            Ok(())
+        }
+    }
+
+    // Mode 3 is indirect: the parameter names a word holding the address
+    struct Indirect;
+
+    impl Mode for Indirect {
+        fn address(&self, word: i64, machine: &dyn Machine) -> i64 {
+            machine.peek(word as usize)
+        }
+    }
+
+    fn dialect(printed: &Arc<Mutex<Vec<i64>>>) -> Dialect {
+        let mut dialect = Dialect::new();
+        dialect.define_opcode(10, Assert);
+        dialect.define_opcode(11, Print(printed.clone()));
+        dialect.define_opcode(12, IncrementAndLoop);
+        dialect.define_mode(3, Indirect);
+        dialect
+    }
+
+    #[test]
+    fn extension_opcode_test() {
+        let printed = Arc::new(Mutex::new(vec!()));
+        // 0: PRN [12]; 2: INC [12], [12], looping until [12] is 0; 5: ASSERT [12], #0; 8: OUT [12]; 10: HLT
+        let source = "11,12,12,12,12,1010,12,0,4,12,99,0,-3";
+        assert_eq!(Program::from_str(source).step(), Err(Error::UnknownOpcode { instruction_pointer: 0, opcode: 11 }));
+
+        let mut program = Program::from_str(source);
+        program.set_dialect(dialect(&printed));
+        assert_eq!(program.run(&[], |_| ()), Ok(Some(0)));
+        assert_eq!(*printed.lock().unwrap(), [-3, -2, -1]);
+
+        // the cache and the uncached decoder agree
+        let mut uncached = Program::from_str(source);
+        uncached.set_dialect(dialect(&printed));
+        uncached.set_instruction_cache(false);
+        assert_eq!(uncached.run(&[], |_| ()), Ok(Some(0)));
+    }
+
+    #[test]
+    fn listing_test() {
+        let printed = Arc::new(Mutex::new(vec!()));
+        let mut program = Program::from_str("11,12,12,12,12,1010,12,0,311,4,99");
+        assert!(program.disassemble().starts_with("    0: DB 11"));
+        program.set_dialect(dialect(&printed));
+        assert_eq!(program.disassemble(), [
+            "    0: PRN [12]                    ; 11,12",
+            "    2: INC [12], [12]              ; 12,12,12",
+            "    5: ASSERT [12], #0             ; 1010,12,0",
+            "    8: PRN m3:4                    ; 311,4",
+            "   10: HLT                         ; 99"
+        ].join("\n"));
+        assert_eq!(program.decode(5).text(), "ASSERT [12], #0");
+
+        // the analysis, and so coverage and profiles, read the same instructions
+        let analysis = program.analyse();
+        assert_eq!(analysis.blocks.len(), 1);
+        assert_eq!(analysis.blocks[&0].lines.len(), 5);
+        assert_eq!(crate::coverage::Coverage::new(&program).summary().instructions, 5);
+    }
+
+    #[test]
+    fn failure_test() {
+        let printed = Arc::new(Mutex::new(vec!()));
+        let mut program = Program::from_str("10,5,6,99,0,7,8");
+        program.set_dialect(dialect(&printed));
+        let err = program.step().unwrap_err();
+        assert_eq!(err, Error::Extension { instruction_pointer: 0, opcode: 10, message: "assertion failed: 7 != 8".to_string() });
+        assert_eq!(err.to_string(), "assertion failed: 7 != 8 (instruction 10 at 0)");
+
+        // INC #4, #0 tries to write in immediate mode, and so does nothing
+        let mut program = Program::from_str("11112,4,0,99");
+        program.set_dialect(dialect(&printed));
+        assert_eq!(program.step(), Err(Error::ImmediateWrite { instruction_pointer: 0, opcode: 11112 }));
+        assert_eq!(program.instruction_pointer(), 0);
+    }
+
+    #[test]
+    fn extension_mode_test() {
+        let printed = Arc::new(Mutex::new(vec!()));
+        // 0: ADD, with its first and last parameters indirect: adds 1 to the word [5] points at, and
+        // stores the sum where [6] points
+        let mut program = Program::from_str("31301,5,1,6,99,7,8,41,0");
+        assert_eq!(program.step(), Err(Error::BadParameterMode { instruction_pointer: 0, opcode: 31301, mode: 3 }));
+
+        program.set_dialect(dialect(&printed));
+        let mut events: Vec<Event> = vec!();
+        assert_eq!(program.step_observed(&mut |e: &Event| events.push(e.clone())), Ok(State::Done));
+        assert_eq!(program.peek(8), 42);
+        assert_eq!(events[0].instruction, Instruction::Add {
+            m1: ParameterMode::Extension(3), m2: ParameterMode::Immediate, m3: ParameterMode::Extension(3)
+        });
+        assert_eq!(events[0].operands, [Some(41), Some(1), Some(8)]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+
+use crate::{Instruction, ParameterMode, Program};
+use crate::dialect::Dialect;
+use crate::memory::Memory;
+
+pub enum Item {
+    Instruction(Instruction, Vec<i64>),
+    Data(i64)
+}
+
+pub struct Line {
+    pub address: usize,
+    pub item: Item
+}
+
+impl Line {
+    // Number of memory words covered by this line
+    pub fn width(&self) -> usize {
+        match &self.item {
+            Item::Instruction(_, params) => 1 + params.len(),
+            Item::Data(_) => 1
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match &self.item {
+            Item::Data(word) => format!("DB {}", word),
+            Item::Instruction(instruction, params) => {
+                let operands: Vec<String> = instruction.parameter_modes().iter().zip(params)
+                    .map(|(mode, word)| render_operand(mode, *word)).collect();
+                if operands.is_empty() {
+                    instruction.mnemonic().to_string()
+                } else {
+                    format!("{} {}", instruction.mnemonic(), operands.join(", "))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match &self.item {
+            Item::Data(_) => write!(writer, "{:>5}: {}", self.address, self.text()),
+            Item::Instruction(instruction, params) => {
+                let mut raw = vec!(instruction.encode().to_string());
+                raw.extend(params.iter().map(|p| p.to_string()));
+                write!(writer, "{:>5}: {:<28}; {}", self.address, self.text(), raw.join(","))
+            }
+        }
+    }
+}
+
+pub fn render_operand(mode: &ParameterMode, word: i64) -> String {
+    match mode {
+        ParameterMode::Positional => format!("[{}]", word),
+        ParameterMode::Immediate => format!("#{}", word),
+        ParameterMode::Relative if word < 0 => format!("rb-{}", word.unsigned_abs()),
+        ParameterMode::Relative => format!("rb+{}", word),
+        ParameterMode::Extension(digit) => format!("m{}:{}", digit, word)
+    }
+}
+
+// Decodes the word at `address`. Words are listed as data unless they are the canonical encoding
+// of an instruction whose parameters all fit in memory, so that the listing reassembles exactly.
+pub fn decode(memory: &[i64], address: usize) -> Line {
+    decode_in(memory, address, None)
+}
+
+// Like decode, also recognising a dialect's extension opcodes and modes
+pub fn decode_in(memory: &[i64], address: usize, dialect: Option<&Dialect>) -> Line {
+    let word = memory[address];
+    let item = match Instruction::decode(address, &word, dialect) {
+        Ok(instruction) if instruction.encode() == word && address + instruction.arity() < memory.len() => {
+            Item::Instruction(instruction, memory[address + 1..=address + instruction.arity()].to_vec())
+        },
+        _ => Item::Data(word)
+    };
+    Line { address, item }
+}
+
+// Linear sweep from address 0; data words are skipped one at a time
+pub fn disassemble(memory: &[i64]) -> Vec<Line> {
+    disassemble_in(memory, None)
+}
+
+pub fn disassemble_in(memory: &[i64], dialect: Option<&Dialect>) -> Vec<Line> {
+    let mut lines = vec!();
+    let mut address = 0;
+    while address < memory.len() {
+        let line = decode_in(memory, address, dialect);
+        address += line.width();
+        lines.push(line);
+    }
+    lines
+}
+
+impl<M: Memory> Program<M> {
+    pub fn disassemble(&self) -> String {
+        disassemble_in(&self.image(), self.dialect()).iter().map(|line| line.to_string()).collect::<Vec<String>>().join("\n")
+    }
+
+    // Decodes a single instruction; words past the end of memory read as zero
+    pub fn decode(&self, address: usize) -> Line {
+        let window: Vec<i64> = (address..address + 4).map(|idx| self.peek(idx)).collect();
+        let mut line = decode_in(&window, 0, self.dialect());
+        line.address = address;
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(code: &str) -> Vec<String> {
+        disassemble(&Program::from_str(code).memory).iter().map(|line| line.text()).collect()
+    }
+
+    #[test]
+    fn mnemonic_test() {
+        assert_eq!(texts("3,9,8,9,10,9,4,9,99,-1,8"),
+            ["IN [9]", "EQ [9], [10], [9]", "OUT [9]", "HLT", "DB -1", "DB 8"]);
+        assert_eq!(texts("1,0,0,0,2,0,0,0,5,0,0,6,0,0,7,0,0,0,9,0"),
+            ["ADD [0], [0], [0]", "MUL [0], [0], [0]", "JT [0], [0]", "JF [0], [0]", "LT [0], [0], [0]", "ARB [0]"]);
+    }
+
+    #[test]
+    fn operand_mode_test() {
+        assert_eq!(texts("21101,5,-3,7,109,19,204,-34,99"),
+            ["ADD #5, #-3, rb+7", "ARB #19", "OUT rb-34", "HLT"]);
+    }
+
+    #[test]
+    fn data_test() {
+        // truncated instruction
+        assert_eq!(texts("1101,1"), ["DB 1101", "DB 1"]);
+        // unknown opcode, bad mode, non-canonical encoding
+        assert_eq!(texts("42,301,1003,99"), ["DB 42", "DB 301", "DB 1003", "HLT"]);
+        assert_eq!(texts("-1,104,-99"), ["DB -1", "OUT #-99"]);
+    }
+
+    #[test]
+    fn listing_test() {
+        let program = Program::from_str("1002,4,3,4,33");
+        assert_eq!(program.disassemble(), [
+            "    0: MUL [4], #3, [4]            ; 1002,4,3,4",
+            "    4: DB 33"
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,134 @@
+// Runs a machine as a tokio task (needs the `async` feature). Inputs arrive on an mpsc channel and
+// outputs are sent on another; a machine that needs input awaits the channel rather than stopping
+// with State::AwaitingInput, so machines compose with each other and with other async code.
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::memory::Memory;
+use crate::{Error, Program, State};
+
+// Channel capacity used by spawn
+pub const CAPACITY: usize = 64;
+// Steps a machine takes before yielding to the runtime, so that a long computation between
+// outputs does not hold up the other tasks on its worker
+pub const SLICE: u64 = 10_000;
+
+// The channel ends of a spawned machine. Dropping `input` tells the machine no more input is
+// coming; the task resolves to the machine's final output, as `Program::run` does.
+pub struct Handle {
+    pub input: mpsc::Sender<i64>,
+    pub output: mpsc::Receiver<i64>,
+    pub task: JoinHandle<Result<Option<i64>, Error>>
+}
+
+impl<M: Memory> Program<M> {
+    // Runs until the program halts. Outputs nobody is receiving any more are dropped; running out
+    // of input, because every sender was dropped, is Error::NoInput.
+    pub async fn run_async(&mut self, mut input: mpsc::Receiver<i64>, output: mpsc::Sender<i64>) -> Result<Option<i64>, Error> {
+        loop {
+            let state = self.await_slice()?;
+            match state {
+                State::Output(x) | State::OutputAwaitingInput(x) => {
+                    let _ = output.send(x).await;
+                },
+                State::AwaitingInput => match input.recv().await {
+                    Some(x) => self.read_input(x),
+                    None => return Err(self.no_input())
+                },
+                State::Done => return Ok(self.return_code),
+                State::OutOfFuel if self.fuel != Some(0) => tokio::task::yield_now().await,
+                State::OutOfFuel | State::TimedOut | State::InfiniteLoop(_) => return Err(self.interrupted(&state)),
+                State::Running => continue
+            }
+        }
+    }
+
+    // await_output for at most SLICE steps, charged to the fuel set by set_fuel; State::OutOfFuel
+    // with fuel left over means only the slice ran out
+    fn await_slice(&mut self) -> Result<State, Error> {
+        let budget = self.fuel;
+        let slice = budget.map_or(SLICE, |fuel| fuel.min(SLICE));
+        self.fuel = Some(slice);
+        let state = self.await_output();
+        let used = slice - self.fuel.unwrap_or(0);
+        self.fuel = budget.map(|fuel| fuel - used);
+        state
+    }
+}
+
+impl<M: Memory + Send + 'static> Program<M> {
+    // Spawns the program on the current tokio runtime
+    pub fn spawn(mut self) -> Handle {
+        let (input, input_rx) = mpsc::channel(CAPACITY);
+        let (output_tx, output) = mpsc::channel(CAPACITY);
+        let task = tokio::spawn(async move { self.run_async(input_rx, output_tx).await });
+        Handle { input, output, task }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the day 7 feedback-loop example, whose best phase setting 9,8,7,6,5 gives 139629729
+    const FEEDBACK: &str = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+
+    #[tokio::test]
+    async fn spawn_test() {
+        let mut handle = Program::from_str("3,9,1002,9,2,9,4,9,1105,1,0").spawn();
+        for x in 1..=3 {
+            handle.input.send(x).await.unwrap();
+            assert_eq!(handle.output.recv().await, Some(2 * x));
+        }
+        drop(handle.input);
+        assert_eq!(handle.task.await.unwrap(), Err(Error::NoInput { instruction_pointer: 0, opcode: 3 }));
+    }
+
+    #[tokio::test]
+    async fn yield_test() {
+        // counts to a million before its one output, taking many slices on a single-threaded runtime
+        let count = "1001,15,1,15,1007,15,1000000,16,1005,16,0,4,15,99,0,0";
+        let mut handle = Program::from_str(count).spawn();
+        let other = tokio::spawn(async { 17 });
+        assert_eq!(other.await.unwrap(), 17);
+        // the other task finished while the machine was still counting
+        assert!(handle.output.try_recv().is_err());
+        assert_eq!(handle.output.recv().await, Some(1000000));
+        assert_eq!(handle.task.await.unwrap(), Ok(Some(1000000)));
+
+        // the program's own fuel still runs out, slices or not
+        let mut program = Program::from_str(count);
+        program.set_fuel(Some(25_000));
+        let (_input, input_rx) = mpsc::channel(CAPACITY);
+        let (output_tx, _output) = mpsc::channel(CAPACITY);
+        assert_eq!(program.run_async(input_rx, output_tx).await, Err(Error::OutOfFuel { instruction_pointer: 4, opcode: 1007 }));
+        assert_eq!(program.fuel(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn ring_test() {
+        let program = Program::from_str(FEEDBACK);
+        // each amplifier reads from the one before it, and the first from the last
+        let channels: Vec<(mpsc::Sender<i64>, mpsc::Receiver<i64>)> = (0..5).map(|_| mpsc::channel(CAPACITY)).collect();
+        let (senders, receivers): (Vec<mpsc::Sender<i64>>, Vec<mpsc::Receiver<i64>>) = channels.into_iter().unzip();
+        for (sender, phase) in senders.iter().zip(vec!(9, 8, 7, 6, 5)) {
+            sender.send(phase).await.unwrap();
+        }
+        senders[0].send(0).await.unwrap();
+
+        let mut tasks = vec!();
+        for (idx, input) in receivers.into_iter().enumerate() {
+            let mut amplifier = program.clone();
+            let output = senders[(idx + 1) % 5].clone();
+            tasks.push(tokio::spawn(async move { amplifier.run_async(input, output).await }));
+        }
+        drop(senders);
+
+        let mut results = vec!();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        assert_eq!(results.last(), Some(&Ok(Some(139629729))));
+    }
+}
@@ -0,0 +1,200 @@
+// Fuzzing the interpreter. Random programs, built mostly from real opcodes, small addresses and
+// extreme values, are stepped under a budget; any panic is a bug, since every misbehaving program
+// should end in an Error. A failing case is shrunk to a small one that still panics, in the
+// text form used by the regression list below:
+//
+//   109,-1,204,0;7
+//
+// which is the program, a semicolon and the inputs queued for it.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::Program;
+
+// Steps allowed for each case
+pub const STEPS: usize = 1000;
+// Keeps wild writes from allocating gigabytes of dense memory
+const MAX_ADDRESS: usize = (1 << 16) - 1;
+
+// splitmix64; reproducible from its seed, which is all a fuzzer needs
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let x = (self.0 ^ (self.0 >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    // Uniform enough in 0..n for small n
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Case {
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        let join = |words: &[i64]| words.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",");
+        write!(writer, "{};{}", join(&self.program), join(&self.inputs))
+    }
+}
+
+impl Case {
+    pub fn parse(line: &str) -> Option<Case> {
+        let split = |s: &str| -> Option<Vec<i64>> { s.split(',').filter(|s| !s.trim().is_empty()).map(|s| s.trim().parse().ok()).collect() };
+        let (program, inputs) = line.split_once(';')?;
+        Some(Case { program: split(program)?, inputs: split(inputs)? })
+    }
+
+    pub fn generate(rng: &mut Rng) -> Case {
+        let len = 1 + rng.below(64);
+        let program: Vec<i64> = (0..len).map(|_| word(rng, len as i64)).collect();
+        let inputs = (0..rng.below(5)).map(|_| word(rng, len as i64)).collect();
+        Case { program, inputs }
+    }
+
+    // Steps the case's program; the panic message, if it panics
+    pub fn run(&self) -> Result<(), String> {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut program: Program = Program::from_words(self.program.clone());
+            program.set_max_address(MAX_ADDRESS);
+            for input in &self.inputs {
+                program.read_input(*input);
+            }
+            for _ in 0..STEPS {
+                if program.is_terminated() || program.step().is_err() {
+                    break
+                }
+            }
+        }));
+        result.map_err(|payload| {
+            payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic".to_string())
+        })
+    }
+
+    // A smaller case for which `fails` still holds: words and inputs are dropped, then values moved
+    // towards zero, for as long as that keeps it failing
+    pub fn minimize<F: Fn(&Case) -> bool>(&self, fails: F) -> Case {
+        let mut case = self.clone();
+        loop {
+            let mut shrunk = false;
+            for candidate in case.candidates() {
+                if fails(&candidate) {
+                    case = candidate;
+                    shrunk = true;
+                    break
+                }
+            }
+            if !shrunk {
+                return case
+            }
+        }
+    }
+
+    // Every case one step smaller than this one, most promising first
+    fn candidates(&self) -> Vec<Case> {
+        let mut candidates = vec!();
+        for i in (0..self.program.len()).rev() {
+            let mut program = self.program.clone();
+            program.remove(i);
+            candidates.push(Case { program, inputs: self.inputs.clone() });
+        }
+        for i in (0..self.inputs.len()).rev() {
+            let mut inputs = self.inputs.clone();
+            inputs.remove(i);
+            candidates.push(Case { program: self.program.clone(), inputs });
+        }
+        let smaller = |x: i64| vec!(0, x / 2, x - x.signum());
+        for i in 0..self.program.len() {
+            for x in smaller(self.program[i]) {
+                if x != self.program[i] {
+                    let mut program = self.program.clone();
+                    program[i] = x;
+                    candidates.push(Case { program, inputs: self.inputs.clone() });
+                }
+            }
+        }
+        for i in 0..self.inputs.len() {
+            for x in smaller(self.inputs[i]) {
+                if x != self.inputs[i] {
+                    let mut inputs = self.inputs.clone();
+                    inputs[i] = x;
+                    candidates.push(Case { program: self.program.clone(), inputs });
+                }
+            }
+        }
+        candidates
+    }
+}
+
+// Cases the fuzzer found to panic, minimized; each must now end in an error or halt
+pub const REGRESSIONS: &[&str] = &[
+    // a relative address beyond i64
+    "21109,1,2204,9223372036854775807;",
+    // a relative base beyond i64
+    "1209,0,9,6,0,0,9223372036854774599;"
+];
+
+const EXTREMES: [i64; 6] = [i64::MIN, i64::MIN + 1, -1 << 32, 1 << 32, i64::MAX - 1, i64::MAX];
+
+fn word(rng: &mut Rng, len: i64) -> i64 {
+    match rng.below(10) {
+        0..=3 => {
+            let opcode = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99][rng.below(10) as usize];
+            // mostly valid modes, now and then a bad one
+            let modes = (0..3).fold(0, |acc, _| 10 * acc + if rng.below(8) == 0 { rng.below(10) } else { rng.below(3) }) as i64;
+            modes * 100 + opcode
+        },
+        4..=6 => rng.below(len as u64 + 8) as i64,
+        7 => -(rng.below(8) as i64) - 1,
+        8 => EXTREMES[rng.below(EXTREMES.len() as u64) as usize],
+        _ => rng.next_u64() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regression_test() {
+        for line in REGRESSIONS {
+            let case = Case::parse(line).unwrap();
+            assert_eq!(case.run(), Ok(()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn fuzz_test() {
+        let mut rng = Rng::new(2019);
+        for _ in 0..2000 {
+            let case = Case::generate(&mut rng);
+            if let Err(message) = case.run() {
+                let small = case.minimize(|c| c.run().is_err());
+                panic!("{} panics: {}", small, message);
+            }
+        }
+    }
+
+    #[test]
+    fn minimize_test() {
+        let case = Case::parse("1,2,3,400,5,6;7,8").unwrap();
+        assert_eq!(case.to_string(), "1,2,3,400,5,6;7,8");
+        let small = case.minimize(|c| c.program.iter().any(|&x| x >= 100));
+        assert_eq!(small, Case { program: vec!(100), inputs: vec!() });
+    }
+}
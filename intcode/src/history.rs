@@ -0,0 +1,181 @@
+// An undo log for stepping a machine backwards. Attach a History as the observer of every step
+// (step_observed, await_output_observed or run_observed); stepping the machine without it makes the
+// log stale.
+
+use std::collections::VecDeque;
+
+use crate::memory::Memory;
+use crate::trace::{Event, Observer, Write};
+use crate::Program;
+
+struct Entry {
+    step: u64,
+    address: usize,
+    write: Option<Write>,
+    relative_base: Option<i64>,
+    input: Option<i64>,
+    output: Option<i64>
+}
+
+// The most recent write to an address
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LastWrite {
+    pub step: u64,
+    pub instruction: usize,
+    pub write: Write
+}
+
+pub struct History {
+    entries: VecDeque<Entry>,
+    limit: Option<usize>,
+    steps: u64,
+    // The return code from before the oldest entry
+    return_code: Option<i64>
+}
+
+impl History {
+    pub fn new<M: Memory>(program: &Program<M>) -> History {
+        History { entries: VecDeque::new(), limit: None, steps: 0, return_code: program.return_code }
+    }
+
+    // Keeps only the most recent `limit` steps
+    pub fn with_limit<M: Memory>(program: &Program<M>, limit: usize) -> History {
+        History { limit: Some(limit), ..History::new(program) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Number of steps recorded so far, less those undone
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Undoes the most recent step; false when there is nothing left to undo
+    pub fn step_back<M: Memory>(&mut self, program: &mut Program<M>) -> bool {
+        let entry = match self.entries.pop_back() {
+            Some(entry) => entry,
+            None => return false
+        };
+        if let Some(write) = entry.write {
+            program.store(write.address, write.old);
+        }
+        if let Some(relative_base) = entry.relative_base {
+            program.relative_base = relative_base;
+        }
+        if let Some(input) = entry.input {
+            program.input_buffer.push_front(input);
+        }
+        if entry.output.is_some() {
+            program.return_code = self.entries.iter().rev().find_map(|e| e.output).or(self.return_code);
+        }
+        program.instruction_pointer = entry.address;
+        self.steps = entry.step;
+        true
+    }
+
+    // Steps back until just before the most recent execution of the instruction at `address`, and
+    // returns how many steps were undone. Nothing is undone if that instruction is not in the log.
+    pub fn run_back_to<M: Memory>(&mut self, program: &mut Program<M>, address: usize) -> Option<usize> {
+        let depth = self.entries.iter().rev().position(|entry| entry.address == address)? + 1;
+        for _ in 0..depth {
+            self.step_back(program);
+        }
+        Some(depth)
+    }
+
+    pub fn last_writer(&self, address: usize) -> Option<LastWrite> {
+        self.entries.iter().rev().find_map(|entry| match entry.write {
+            Some(write) if write.address == address => Some(LastWrite { step: entry.step, instruction: entry.address, write }),
+            _ => None
+        })
+    }
+}
+
+impl Observer for History {
+    fn on_step(&mut self, event: &Event) {
+        if self.limit == Some(self.entries.len()) {
+            match self.entries.pop_front() {
+                Some(Entry { output: Some(out), .. }) => self.return_code = Some(out),
+                Some(_) => (),
+                None => return
+            }
+        }
+        self.entries.push_back(Entry {
+            step: self.steps,
+            address: event.address,
+            write: event.write,
+            relative_base: event.relative_base.map(|(old, _)| old),
+            input: event.input,
+            output: event.output
+        });
+        self.steps += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    // reads a number n and counts down from it, as in the snapshot tests
+    const COUNTDOWN: &str = "109,20,203,0,204,0,21201,0,-1,0,1205,0,4,99";
+
+    #[test]
+    fn rewind_test() {
+        let start = Program::from_str(COUNTDOWN);
+        let mut program = start.clone();
+        let mut history = History::new(&program);
+        assert_eq!(program.run_observed(&[3], |_| (), &mut history), Ok(Some(1)));
+        assert_eq!(history.steps(), 11);
+
+        while history.step_back(&mut program) {}
+        assert_eq!(program.instruction_pointer(), 0);
+        assert_eq!(program.relative_base(), 0);
+        // dense memory stays grown, but reads the same
+        assert!((0..32).all(|a| program.peek(a) == start.peek(a)));
+        assert_eq!(program.input_buffer().iter().collect::<Vec<&i64>>(), [&3]);
+        assert_eq!(program.return_code, None);
+
+        // replaying gives the same outputs again
+        let mut outputs = vec!();
+        program.run_observed(&[], |x| outputs.push(x), &mut history).unwrap();
+        assert_eq!(outputs, [3, 2, 1]);
+    }
+
+    #[test]
+    fn run_back_to_test() {
+        let mut program = Program::from_str(COUNTDOWN);
+        let mut history = History::new(&program);
+        program.read_input(3);
+        for _ in 0..3 {
+            program.await_output_observed(&mut history).unwrap();
+        }
+        assert_eq!(history.last_writer(20), Some(LastWrite { step: 6, instruction: 6, write: Write { address: 20, old: 2, new: 1 } }));
+
+        // back to before the second output
+        assert_eq!(history.run_back_to(&mut program, 4), Some(1));
+        assert_eq!(history.run_back_to(&mut program, 4), Some(3));
+        assert_eq!(program.instruction_pointer(), 4);
+        assert_eq!(program.return_code, Some(3));
+        assert_eq!(program.await_output(), Ok(State::Output(2)));
+        assert_eq!(history.run_back_to(&mut program, 99), None);
+    }
+
+    #[test]
+    fn limit_test() {
+        let mut program = Program::from_str(COUNTDOWN);
+        let mut history = History::with_limit(&program, 4);
+        program.run_observed(&[3], |_| (), &mut history).unwrap();
+        assert_eq!(history.len(), 4);
+        while history.step_back(&mut program) {}
+        assert_eq!(history.steps(), 7);
+        assert_eq!(program.instruction_pointer(), 10);
+        assert_eq!(program.return_code, Some(2));
+    }
+}
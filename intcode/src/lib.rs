@@ -1,220 +1,425 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::VecDeque;
 use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub mod ascii;
+pub mod analysis;
+pub mod asm;
+pub mod coverage;
+mod cycle;
+pub mod dialect;
+pub mod disasm;
+#[cfg(feature = "async")]
+pub mod driver;
+pub mod fuzz;
+pub mod history;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod session;
+pub mod snapshot;
+pub mod stream;
+pub mod symbolic;
+pub mod trace;
+pub mod transpile;
+
+use dialect::Dialect;
+use memory::{Memory, SharedVec};
+use trace::{Event, Observer, Write};
 
 #[derive(Clone)]
-pub struct Program {
-    memory: Vec<i64>,
+pub struct Program<M: Memory = Vec<i64>> {
+    memory: M,
+    max_address: usize,
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    arithmetic: Arithmetic,
+    // Extra opcodes and parameter modes; None is the standard instruction set
+    dialect: Option<Arc<Dialect>>,
+    // Each word decoded as an instruction, by address; empty while the cache is off
+    decoded: SharedVec<Option<Instruction>>,
     instruction_pointer: usize,
     relative_base: i64,
     return_code: Option<i64>,
-    input_buffer: VecDeque<i64>
+    input_buffer: VecDeque<i64>,
+    // Present while loop detection is on
+    loop_detector: Option<cycle::LoopDetector>
 }
 
 impl Program {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(line: &str) -> Program {
-        let memory: Vec<i64> = line.split(",")
-            .flat_map(|s| i64::from_str_radix(s, 10).ok()).collect();
-        Program { memory,
+        Program::parse(line)
+    }
+}
+
+impl<M: Memory> Program<M> {
+    // Like from_str, for any memory backend: `Program::<Paged>::parse(line)`
+    pub fn parse(line: &str) -> Program<M> {
+        let words: Vec<i64> = line.split(',')
+            .flat_map(|s| s.trim().parse().ok()).collect();
+        Program::from_words(words)
+    }
+
+    pub fn from_words(words: Vec<i64>) -> Program<M> {
+        let mut program = Program {
+            memory: M::from_words(words),
+            max_address: memory::DEFAULT_MAX_ADDRESS,
+            fuel: None,
+            deadline: None,
+            arithmetic: Arithmetic::Checked,
+            dialect: None,
+            decoded: SharedVec::default(),
             instruction_pointer: 0,
             relative_base: 0,
             return_code: None,
-            input_buffer: VecDeque::new()
+            input_buffer: VecDeque::new(),
+            loop_detector: None
+        };
+        program.set_instruction_cache(true);
+        program
+    }
+
+    // With the cache on, every word of memory is decoded up front (up to MAX_CACHED_ADDRESS) so that
+    // steps need not parse opcodes again; a write re-decodes the word it changes, which keeps
+    // self-modifying programs correct.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.decoded = if enabled {
+            let end = usize::min(self.memory.len(), MAX_CACHED_ADDRESS + 1);
+            SharedVec::from_vec((0..end).map(|address| self.decode_word(address, self.memory.read(address)).ok()).collect())
+        } else {
+            SharedVec::default()
+        };
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    // Replaces the instruction set's extensions; see the dialect module
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = Some(Arc::new(dialect));
+        let cached = !self.decoded.is_empty();
+        self.set_instruction_cache(cached);
+    }
+
+    pub fn dialect(&self) -> Option<&Dialect> {
+        self.dialect.as_deref()
+    }
+
+    // Reads and writes beyond the maximum address fail with AddressOutOfRange
+    pub fn set_max_address(&mut self, max_address: usize) {
+        self.max_address = max_address;
+    }
+
+    pub fn max_address(&self) -> usize {
+        self.max_address
+    }
+
+    // Limits how many more instructions await_output and run may execute; None removes the limit
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // await_output and run stop once the deadline has passed; None removes the deadline
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // Stops await_output and run with State::InfiniteLoop when the machine returns to a state it
+    // has been in since it last read input or wrote output; see the cycle module
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detector = if enabled {
+            Some(cycle::LoopDetector::new(&self.memory, self.instruction_pointer, self.relative_base))
+        } else {
+            None
+        };
+    }
+
+    pub fn loop_detection(&self) -> bool {
+        self.loop_detector.is_some()
+    }
+
+    fn current_instruction(&self) -> Result<Instruction, Error> {
+        match self.decoded.get(self.instruction_pointer) {
+            Some(Some(instruction)) => Ok(*instruction),
+            _ => self.decode_word(self.instruction_pointer, self.peek(self.instruction_pointer))
         }
     }
 
-    fn current_instruction(&self) -> Option<Instruction> {
-        match self.memory.get(self.instruction_pointer) {
-            None => {
-                eprintln!("No instruction found at {}", &self.instruction_pointer);
-                None
-            },
-            Some(x) => Instruction::parse(x)
+    fn decode_word(&self, address: usize, word: i64) -> Result<Instruction, Error> {
+        Instruction::decode(address, &word, self.dialect.as_deref())
+    }
+
+    fn store(&mut self, address: usize, value: i64) -> i64 {
+        if let Some(decoded) = self.decoded.get_mut(address) {
+            *decoded = Instruction::decode(address, &value, self.dialect.as_deref()).ok();
         }
+        self.memory.write(address, value)
     }
 
-    fn get(&mut self, idx: usize, mode: &ParameterMode) -> Option<i64> {
-        let read_idx = match mode {
-            ParameterMode::Immediate => idx,
-            ParameterMode::Positional => self.memory[idx] as usize,
-            ParameterMode::Relative => self.relative_idx(idx)
+    pub fn peek(&self, idx: usize) -> i64 {
+        self.memory.read(idx)
+    }
+
+    fn address(&self, idx: usize, mode: &ParameterMode) -> Result<usize, Error> {
+        let address = match mode {
+            ParameterMode::Immediate => return Ok(idx),
+            ParameterMode::Positional => self.peek(idx),
+            ParameterMode::Relative => self.relative_base.checked_add(self.peek(idx)).ok_or_else(|| self.overflow())?,
+            ParameterMode::Extension(digit) => match self.dialect.as_ref().and_then(|d| d.mode(*digit)) {
+                Some(extension) => extension.address(self.peek(idx), self),
+                None => return Err(Error::BadParameterMode {
+                    instruction_pointer: self.instruction_pointer,
+                    opcode: self.peek(self.instruction_pointer),
+                    mode: *digit
+                })
+            }
         };
-        if read_idx >= self.memory.len() {
-            self.memory.resize(read_idx + 1, 0);
+        let address = usize::try_from(address).map_err(|_| Error::NegativeAddress {
+            instruction_pointer: self.instruction_pointer,
+            opcode: self.peek(self.instruction_pointer),
+            address
+        })?;
+        if address > self.max_address {
+            return Err(Error::AddressOutOfRange {
+                instruction_pointer: self.instruction_pointer,
+                opcode: self.peek(self.instruction_pointer),
+                address,
+                max_address: self.max_address
+            })
+        }
+        Ok(address)
+    }
+
+    // Adds or multiplies for ADD and MUL, according to the arithmetic setting
+    fn combine(&self, x: i64, y: i64, checked: fn(i64, i64) -> Option<i64>, wrapping: fn(i64, i64) -> i64) -> Result<i64, Error> {
+        match self.arithmetic {
+            Arithmetic::Wrapping => Ok(wrapping(x, y)),
+            Arithmetic::Checked => checked(x, y).ok_or_else(|| self.overflow())
+        }
+    }
+
+    fn overflow(&self) -> Error {
+        Error::Overflow {
+            instruction_pointer: self.instruction_pointer,
+            opcode: self.peek(self.instruction_pointer)
         }
-        self.memory.get(read_idx).map(|x| *x)
     }
 
-    fn relative_idx(&self, idx: usize) -> usize {
-        (self.relative_base + self.memory[idx]) as usize
+    fn get(&self, idx: usize, mode: &ParameterMode) -> Result<i64, Error> {
+        self.address(idx, mode).map(|read_idx| self.peek(read_idx))
     }
 
     pub fn read_input(&mut self, input: i64) {
         self.input_buffer.push_back(input)
     }
 
-    fn set(&mut self, idx: usize, value: i64, mode: &ParameterMode) {
-        let write_idx = match mode {
-            ParameterMode::Positional => self.memory[idx] as usize,
-            ParameterMode::Relative => self.relative_idx(idx),
-            _ => {
-                eprintln!("Setting values in Immediate mode is not supported!");
-                idx
-            }
-        };
-        if write_idx >= self.memory.len() {
-            self.memory.resize(write_idx + 1, 0);
+    fn set(&mut self, idx: usize, value: i64, mode: &ParameterMode) -> Result<Write, Error> {
+        if let ParameterMode::Immediate = mode {
+            return Err(Error::ImmediateWrite {
+                instruction_pointer: self.instruction_pointer,
+                opcode: self.peek(self.instruction_pointer)
+            })
         }
-        self.memory[write_idx] = value;
+        let write_idx = self.address(idx, mode)?;
+        let old = self.store(write_idx, value);
+        Ok(Write { address: write_idx, old, new: value })
     }
 
-    fn step(&mut self) -> State {
+    // Executes a single instruction. A failed step leaves the machine unchanged.
+    pub fn step(&mut self) -> Result<State, Error> {
+        self.step_observed(&mut ())
+    }
+
+    pub fn step_observed<O: Observer>(&mut self, observer: &mut O) -> Result<State, Error> {
         enum StepResult {
             Halt,
             Jump,
-            Crash,
-            Fwd(usize),
-            Output(i64)
+            Fwd(usize)
         }
 
-        fn perform_jump_if(this: &mut Program, nonzero: bool, m1: &ParameterMode, m2: &ParameterMode) -> StepResult {
-            match this.get(this.instruction_pointer + 1, m1) {
-                None => StepResult::Crash,
-                Some(p1) if (p1 != 0) == nonzero => match this.get(this.instruction_pointer + 2, m2) {
-                    None => StepResult::Crash,
-                    Some(p2) => match usize::try_from(p2) {
-                        Err(_) => StepResult::Crash,
-                        Ok(p2) => {
-                            this.instruction_pointer = p2;
-                            StepResult::Jump
-                        }
-                    }
-                },
-                _ => StepResult::Fwd(3)
+        fn perform_jump_if<M: Memory>(this: &mut Program<M>, event: &mut Event, nonzero: bool, m1: &ParameterMode, m2: &ParameterMode) -> Result<StepResult, Error> {
+            let p1 = this.get(this.instruction_pointer + 1, m1)?;
+            event.operands[0] = Some(p1);
+            if (p1 != 0) != nonzero {
+                return Ok(StepResult::Fwd(3))
             }
-        };
-    
-        let step_result = match self.current_instruction() {
-            None => StepResult::Crash,
-            Some(Instruction::Halt) => StepResult::Halt,
-            Some(Instruction::Add { m1, m2, m3 }) => {
-                let addend1 = self.get(self.instruction_pointer + 1, &m1).unwrap();
-                let addend2 = self.get(self.instruction_pointer + 2, &m2).unwrap();
-                self.set(self.instruction_pointer + 3, addend1 + addend2, &m3);
+            let p2 = this.get(this.instruction_pointer + 2, m2)?;
+            event.operands[1] = Some(p2);
+            match usize::try_from(p2) {
+                Err(_) => Err(Error::InvalidJumpTarget {
+                    instruction_pointer: this.instruction_pointer,
+                    opcode: this.peek(this.instruction_pointer),
+                    target: p2
+                }),
+                Ok(p2) => {
+                    this.instruction_pointer = p2;
+                    Ok(StepResult::Jump)
+                }
+            }
+        }
+
+        let ip = self.instruction_pointer;
+        let instruction = self.current_instruction()?;
+        let mut event = Event::new(ip, self.peek(ip), instruction);
+        let step_result = match instruction {
+            Instruction::Halt => StepResult::Halt,
+            Instruction::Add { m1, m2, m3 } => {
+                let addend1 = self.get(ip + 1, &m1)?;
+                let addend2 = self.get(ip + 2, &m2)?;
+                let sum = self.combine(addend1, addend2, i64::checked_add, i64::wrapping_add)?;
+                event.record_write([addend1, addend2], self.set(ip + 3, sum, &m3)?);
                 StepResult::Fwd(4)
             },
-            Some(Instruction::Mult { m1, m2, m3 }) => {
-                let factor1 = self.get(self.instruction_pointer + 1, &m1).unwrap();
-                let factor2 = self.get(self.instruction_pointer + 2, &m2).unwrap();
-                self.set(self.instruction_pointer + 3, factor1 * factor2, &m3);
+            Instruction::Mult { m1, m2, m3 } => {
+                let factor1 = self.get(ip + 1, &m1)?;
+                let factor2 = self.get(ip + 2, &m2)?;
+                let product = self.combine(factor1, factor2, i64::checked_mul, i64::wrapping_mul)?;
+                event.record_write([factor1, factor2], self.set(ip + 3, product, &m3)?);
                 StepResult::Fwd(4)
             },
-            Some(Instruction::Input { m1 }) => {
-                match self.input_buffer.pop_front() {
-                    None => StepResult::Crash,
-                    Some(input) => {
-                        self.set(self.instruction_pointer + 1, input, &m1);
+            Instruction::Input { m1 } => {
+                match self.input_buffer.front() {
+                    None => return Err(Error::NoInput { instruction_pointer: ip, opcode: self.peek(ip) }),
+                    Some(&input) => {
+                        let write = self.set(ip + 1, input, &m1)?;
+                        self.input_buffer.pop_front();
+                        event.operands[0] = Some(write.address as i64);
+                        event.write = Some(write);
+                        event.input = Some(input);
                         StepResult::Fwd(2)
                     }
                 }
             },
-            Some(Instruction::Output { m1 }) => {
-                match self.get(self.instruction_pointer + 1, &m1) {
-                    None => StepResult::Crash,
-                    Some(out) => {
-                        self.return_code = Some(out);
-                        StepResult::Output(out)
-                    }
-                }
+            Instruction::Output { m1 } => {
+                let out = self.get(ip + 1, &m1)?;
+                self.return_code = Some(out);
+                event.operands[0] = Some(out);
+                event.output = Some(out);
+                StepResult::Fwd(2)
             },
-            Some(Instruction::JumpIfTrue { m1, m2 }) => perform_jump_if(self, true, &m1, &m2),
-            Some(Instruction::JumpIfFalse { m1, m2 }) => perform_jump_if(self, false, &m1, &m2),
-            Some(Instruction::LessThan { m1, m2, m3 }) => {
-                match self.get(self.instruction_pointer + 1, &m1).and_then(|p1| self.get(self.instruction_pointer + 2, &m2).map(|p2| (p1, p2))) {
-                    None => StepResult::Crash,
-                    Some((p1, p2)) => {
-                        self.set(self.instruction_pointer + 3, (p1 < p2) as i64, &m3);
-                        StepResult::Fwd(4)
-                    }
-                }
+            Instruction::JumpIfTrue { m1, m2 } => perform_jump_if(self, &mut event, true, &m1, &m2)?,
+            Instruction::JumpIfFalse { m1, m2 } => perform_jump_if(self, &mut event, false, &m1, &m2)?,
+            Instruction::LessThan { m1, m2, m3 } => {
+                let p1 = self.get(ip + 1, &m1)?;
+                let p2 = self.get(ip + 2, &m2)?;
+                event.record_write([p1, p2], self.set(ip + 3, (p1 < p2) as i64, &m3)?);
+                StepResult::Fwd(4)
             },
-            Some(Instruction::Equals { m1, m2, m3 }) => {
-                match self.get(self.instruction_pointer + 1, &m1).and_then(|p1| self.get(self.instruction_pointer + 2, &m2).map(|p2| (p1, p2))) {
-                    None => StepResult::Crash,
-                    Some((p1, p2)) => {
-                        self.set(self.instruction_pointer + 3, (p1 == p2) as i64, &m3);
-                        StepResult::Fwd(4)
-                    }
-                }
+            Instruction::Equals { m1, m2, m3 } => {
+                let p1 = self.get(ip + 1, &m1)?;
+                let p2 = self.get(ip + 2, &m2)?;
+                event.record_write([p1, p2], self.set(ip + 3, (p1 == p2) as i64, &m3)?);
+                StepResult::Fwd(4)
             },
-            Some(Instruction::RelativeBaseAdjust { m1 }) => {
-                match self.get(self.instruction_pointer + 1, &m1) {
-                    None => StepResult::Crash,
-                    Some(p1) => {
-                        self.relative_base += p1;
-                        StepResult::Fwd(2)
-                    }
+            Instruction::RelativeBaseAdjust { m1 } => {
+                let p1 = self.get(ip + 1, &m1)?;
+                let relative_base = self.relative_base.checked_add(p1).ok_or_else(|| self.overflow())?;
+                event.operands[0] = Some(p1);
+                event.relative_base = Some((self.relative_base, relative_base));
+                self.relative_base = relative_base;
+                StepResult::Fwd(2)
+            },
+            Instruction::Extension { opcode, modes, arity, .. } => {
+                let definition = match self.dialect.as_ref().and_then(|d| d.opcode(opcode)) {
+                    Some(definition) => definition,
+                    None => return Err(Error::UnknownOpcode { instruction_pointer: ip, opcode: self.peek(ip) })
+                };
+                let mut context = dialect::Context::new(self, modes, arity);
+                definition.execute(&mut context)?;
+                let effects = context.finish();
+                event.operands = effects.operands;
+                if let Some((address, value)) = effects.write {
+                    let old = self.store(address, value);
+                    event.write = Some(Write { address, old, new: value });
+                }
+                if let Some(input) = effects.input {
+                    self.input_buffer.pop_front();
+                    event.input = Some(input);
                 }
+                if let Some(out) = effects.output {
+                    self.return_code = Some(out);
+                    event.output = Some(out);
+                }
+                // an extension that does not jump moves on past its parameters
+                self.instruction_pointer = effects.jump.unwrap_or(ip + 1 + arity);
+                StepResult::Jump
             }
         };
 
         match step_result {
-            StepResult::Crash => {
-                return State::Crashed;
-            },
-            StepResult::Output(out) => {
-                self.instruction_pointer += 2;
-                match self.current_instruction() {
-                    Some(Instruction::Input { .. }) => {
-                        return State::OutputAwaitingInput(out)
-                    },
-                    _ => {
-                        return State::Output(out)
-                    }
-                }
-            },
-            StepResult::Fwd(len) => {
-                self.instruction_pointer += len;
-            },
-            _ => ()
+            StepResult::Fwd(len) => self.instruction_pointer += len,
+            StepResult::Halt | StepResult::Jump => ()
         };
+        event.next = self.instruction_pointer;
+        observer.on_step(&event);
+
+        if let Some(out) = event.output {
+            return match self.current_instruction() {
+                Ok(Instruction::Input { .. }) => Ok(State::OutputAwaitingInput(out)),
+                _ => Ok(State::Output(out))
+            }
+        }
+        // A malformed next instruction is reported by the step that tries to execute it
         match self.current_instruction() {
-            Some(Instruction::Halt) => State::Done,
-            Some(Instruction::Input { .. }) if self.input_buffer.is_empty() => State::AwaitingInput,
-            None => State::Crashed,
-            _ => State::Running
+            Ok(Instruction::Halt) => Ok(State::Done),
+            Ok(Instruction::Input { .. }) if self.input_buffer.is_empty() => Ok(State::AwaitingInput),
+            _ => Ok(State::Running)
         }
     }
-    
-    pub fn run_and_print(&mut self, inputs: &[i64]) -> Option<i64> {
+
+    pub fn run_and_print(&mut self, inputs: &[i64]) -> Result<Option<i64>, Error> {
         self.run(inputs, |x| {println!("Output: {}", &x)})
     }
 
-    pub fn run<F>(&mut self, inputs: &[i64], mut on_output: F) -> Option<i64>
+    pub fn run<F>(&mut self, inputs: &[i64], on_output: F) -> Result<Option<i64>, Error>
     where F: FnMut(i64) {
+        self.run_observed(inputs, on_output, &mut ())
+    }
+
+    pub fn run_observed<F, O>(&mut self, inputs: &[i64], mut on_output: F, observer: &mut O) -> Result<Option<i64>, Error>
+    where F: FnMut(i64), O: Observer {
         for input in inputs {
             self.read_input(*input);
         }
         loop {
-            let state = self.await_output();
+            let state = self.await_output_observed(observer)?;
             match state {
                 State::Output(out) => {
                     on_output(out);
                     continue
                 },
-                State::Done => return self.return_code,
-                State::Crashed => {
-                    eprintln!("Program reports crashed state");
-                    return self.return_code
-                },
+                State::Done => return Ok(self.return_code),
                 State::AwaitingInput if self.input_buffer.is_empty() => {
-                    eprintln!("Program wants input but none available");
-                    return self.return_code
+                    return Err(self.no_input())
                 },
                 State::AwaitingInput => continue,
+                State::OutOfFuel | State::TimedOut | State::InfiniteLoop(_) => return Err(self.interrupted(&state)),
                 State::OutputAwaitingInput(out) if self.input_buffer.is_empty() => {
                     on_output(out);
-                    eprintln!("Program wants input but none available");
-                    return self.return_code
+                    return Err(self.no_input())
                 },
                 State::OutputAwaitingInput(out) => {
                     on_output(out);
@@ -225,30 +430,127 @@ impl Program {
         }
     }
 
-    pub fn await_output(&mut self) -> State {
-        match self.current_instruction() {
-            None => State::Crashed,
-            Some(Instruction::Input { .. }) if self.input_buffer.is_empty() => State::AwaitingInput,
+    pub(crate) fn no_input(&self) -> Error {
+        Error::NoInput {
+            instruction_pointer: self.instruction_pointer,
+            opcode: self.peek(self.instruction_pointer)
+        }
+    }
+
+    // The error that ends a run stopped by the fuel limit, deadline or loop detection
+    pub(crate) fn interrupted(&self, state: &State) -> Error {
+        let instruction_pointer = self.instruction_pointer;
+        let opcode = self.peek(instruction_pointer);
+        match state {
+            State::TimedOut => Error::TimedOut { instruction_pointer, opcode },
+            State::InfiniteLoop(length) => Error::InfiniteLoop { instruction_pointer, opcode, length: *length },
+            _ => Error::OutOfFuel { instruction_pointer, opcode }
+        }
+    }
+
+    pub fn await_output(&mut self) -> Result<State, Error> {
+        self.await_output_observed(&mut ())
+    }
+
+    pub fn await_output_observed<O: Observer>(&mut self, observer: &mut O) -> Result<State, Error> {
+        match self.current_instruction()? {
+            Instruction::Input { .. } if self.input_buffer.is_empty() => Ok(State::AwaitingInput),
             _ => {
+                let mut steps: u64 = 0;
                 loop {
-                    match self.step() {
-                        State::Running => continue,
-                        state => return state
+                    if self.fuel == Some(0) {
+                        return Ok(State::OutOfFuel)
+                    }
+                    // reading the clock costs more than a step, so only do it now and then
+                    if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && self.deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                        return Ok(State::TimedOut)
+                    }
+                    let (state, cycle) = match self.loop_detector.take() {
+                        None => (self.step_observed(observer)?, None),
+                        Some(mut detector) => {
+                            let mut cycle = None;
+                            let state = self.step_observed(&mut |event: &Event| {
+                                observer.on_step(event);
+                                cycle = detector.observe(event);
+                            });
+                            self.loop_detector = Some(detector);
+                            (state?, cycle)
+                        }
+                    };
+                    steps += 1;
+                    if let Some(fuel) = self.fuel.as_mut() {
+                        *fuel -= 1;
+                    }
+                    match (state, cycle) {
+                        (State::Running, Some(length)) => return Ok(State::InfiniteLoop(length)),
+                        (State::Running, None) => continue,
+                        (state, _) => return Ok(state)
                     }
                 }
             }
         }
     }
 
-    pub fn is_terminated(&self) -> bool {
-        match self.current_instruction() {
-            Some(Instruction::Halt) => true,
-            _ => false
+    // A copy to explore from; with CopyOnWrite memory it shares every page until one of the two
+    // writes to it
+    pub fn fork(&self) -> Program<M> {
+        Program {
+            memory: self.memory.fork(),
+            max_address: self.max_address,
+            fuel: self.fuel,
+            deadline: self.deadline,
+            arithmetic: self.arithmetic,
+            dialect: self.dialect.clone(),
+            decoded: self.decoded.clone(),
+            instruction_pointer: self.instruction_pointer,
+            relative_base: self.relative_base,
+            return_code: self.return_code,
+            input_buffer: self.input_buffer.clone(),
+            loop_detector: self.loop_detector.clone()
         }
     }
 
+    // Identifies the machine's state for deduplicating searches: equal for machines with the same
+    // memory contents, instruction pointer, relative base and pending input. Configuration and
+    // the last output are left out.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.instruction_pointer.hash(&mut hasher);
+        self.relative_base.hash(&mut hasher);
+        self.input_buffer.hash(&mut hasher);
+        self.memory.hash_contents(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        matches!(self.current_instruction(), Ok(Instruction::Halt))
+    }
+
     pub fn overwrite_memory(&mut self, idx: usize, word: i64) {
-        self.memory[idx] = word;
+        self.store(idx, word);
+    }
+
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn input_buffer(&self) -> &VecDeque<i64> {
+        &self.input_buffer
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    // Memory as a dense image for the listings and analyses, which stops at MAX_CACHED_ADDRESS as
+    // the instruction cache does; a sparse memory can reach much further than could be made dense
+    pub(crate) fn image(&self) -> Vec<i64> {
+        let end = usize::min(self.memory.len(), MAX_CACHED_ADDRESS + 1);
+        (0..end).map(|address| self.memory.read(address)).collect()
     }
 }
 
@@ -259,11 +561,107 @@ pub enum State {
     AwaitingInput,
     Running,
     Done,
-    Crashed
+    // The budget set by set_fuel ran out before the program produced output or stopped
+    OutOfFuel,
+    // The deadline set by set_deadline passed before the program produced output or stopped
+    TimedOut,
+    // With loop detection on, the machine came back to an earlier state without any input or output;
+    // it would repeat the cycle of this many steps forever
+    InfiniteLoop(u64)
 }
 
+// How ADD and MUL treat results that do not fit in an i64. Words are always i64: a wider word
+// would need the memory, decoder and every observer to be generic over it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Arithmetic {
+    // Fail with Error::Overflow
+    Checked,
+    // Wrap around, two's complement
+    Wrapping
+}
+
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+const MAX_CACHED_ADDRESS: usize = (1 << 20) - 1;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    UnknownOpcode { instruction_pointer: usize, opcode: i64 },
+    BadParameterMode { instruction_pointer: usize, opcode: i64, mode: i64 },
+    ImmediateWrite { instruction_pointer: usize, opcode: i64 },
+    NegativeAddress { instruction_pointer: usize, opcode: i64, address: i64 },
+    AddressOutOfRange { instruction_pointer: usize, opcode: i64, address: usize, max_address: usize },
+    InvalidJumpTarget { instruction_pointer: usize, opcode: i64, target: i64 },
+    NoInput { instruction_pointer: usize, opcode: i64 },
+    OutOfFuel { instruction_pointer: usize, opcode: i64 },
+    TimedOut { instruction_pointer: usize, opcode: i64 },
+    InfiniteLoop { instruction_pointer: usize, opcode: i64, length: u64 },
+    Overflow { instruction_pointer: usize, opcode: i64 },
+    // Raised by an extension instruction; see the dialect module
+    Extension { instruction_pointer: usize, opcode: i64, message: String }
+}
 
-enum Instruction {
+impl Error {
+    pub fn instruction_pointer(&self) -> usize {
+        match self {
+            Error::UnknownOpcode { instruction_pointer, .. } |
+            Error::BadParameterMode { instruction_pointer, .. } |
+            Error::ImmediateWrite { instruction_pointer, .. } |
+            Error::NegativeAddress { instruction_pointer, .. } |
+            Error::AddressOutOfRange { instruction_pointer, .. } |
+            Error::InvalidJumpTarget { instruction_pointer, .. } |
+            Error::NoInput { instruction_pointer, .. } |
+            Error::OutOfFuel { instruction_pointer, .. } |
+            Error::TimedOut { instruction_pointer, .. } |
+            Error::InfiniteLoop { instruction_pointer, .. } |
+            Error::Overflow { instruction_pointer, .. } |
+            Error::Extension { instruction_pointer, .. } => *instruction_pointer
+        }
+    }
+
+    pub fn opcode(&self) -> i64 {
+        match self {
+            Error::UnknownOpcode { opcode, .. } |
+            Error::BadParameterMode { opcode, .. } |
+            Error::ImmediateWrite { opcode, .. } |
+            Error::NegativeAddress { opcode, .. } |
+            Error::AddressOutOfRange { opcode, .. } |
+            Error::InvalidJumpTarget { opcode, .. } |
+            Error::NoInput { opcode, .. } |
+            Error::OutOfFuel { opcode, .. } |
+            Error::TimedOut { opcode, .. } |
+            Error::InfiniteLoop { opcode, .. } |
+            Error::Overflow { opcode, .. } |
+            Error::Extension { opcode, .. } => *opcode
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { .. } => write!(writer, "unknown opcode"),
+            Error::BadParameterMode { mode, .. } => write!(writer, "bad parameter mode {}", mode),
+            Error::ImmediateWrite { .. } => write!(writer, "write in immediate mode"),
+            Error::NegativeAddress { address, .. } => write!(writer, "negative address {}", address),
+            Error::AddressOutOfRange { address, max_address, .. } => write!(writer, "address {} is beyond the maximum address {}", address, max_address),
+            Error::InvalidJumpTarget { target, .. } => write!(writer, "invalid jump target {}", target),
+            Error::NoInput { .. } => write!(writer, "input requested but none is queued"),
+            Error::OutOfFuel { .. } => write!(writer, "instruction budget exhausted"),
+            Error::TimedOut { .. } => write!(writer, "deadline passed"),
+            Error::InfiniteLoop { length, .. } => write!(writer, "stuck in a loop of {} steps", length),
+            Error::Overflow { .. } => write!(writer, "arithmetic overflow"),
+            Error::Extension { message, .. } => write!(writer, "{}", message)
+        }?;
+        write!(writer, " (instruction {} at {})", self.opcode(), self.instruction_pointer())
+    }
+}
+
+impl error::Error for Error {}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instruction {
     Halt,
     Add { m1: ParameterMode, m2: ParameterMode, m3: ParameterMode },
     Mult { m1: ParameterMode, m2: ParameterMode, m3: ParameterMode },
@@ -273,88 +671,94 @@ enum Instruction {
     JumpIfFalse { m1: ParameterMode, m2: ParameterMode },
     LessThan { m1: ParameterMode, m2: ParameterMode, m3: ParameterMode },
     Equals { m1: ParameterMode, m2: ParameterMode, m3: ParameterMode },
-    RelativeBaseAdjust { m1: ParameterMode }
+    RelativeBaseAdjust { m1: ParameterMode },
+    // An opcode defined by the program's dialect
+    Extension { opcode: i64, mnemonic: &'static str, modes: [ParameterMode; 3], arity: usize }
 }
 
 impl Instruction {
-    fn parse(abcde: &i64) -> Option<Instruction> {
+    fn decode(instruction_pointer: usize, abcde: &i64, dialect: Option<&Dialect>) -> Result<Instruction, Error> {
+        let modes = |arity: usize| ParameterMode::parse_all(instruction_pointer, *abcde, arity, dialect);
         match abcde.rem_euclid(100) {
-            99 => Some(Instruction::Halt),
-            1 => {
-                Instruction::parse_three(abcde / 100).map(|(m1, m2, m3)| {
-                    Instruction::Add { m1, m2, m3 }
-                })
-            },
-            2 => {
-                Instruction::parse_three(abcde / 100).map(|(m1, m2, m3)| {
-                    Instruction::Mult { m1, m2, m3 }
-                })
-            },
-            3 => {
-                Instruction::parse_one(abcde / 100).map(|m1| Instruction::Input { m1 })
-            },
-            4 => {
-                Instruction::parse_one(abcde / 100).map(|m1| Instruction::Output { m1 })
-            },
-            5 => {
-                Instruction::parse_two(abcde / 100).map(|(m1, m2)| {
-                    Instruction::JumpIfTrue { m1, m2 }
-                })
-            },
-            6 => {
-                Instruction::parse_two(abcde / 100).map(|(m1, m2)| {
-                    Instruction::JumpIfFalse { m1, m2 }
-                })
-            },
-            7 => {
-                Instruction::parse_three(abcde / 100).map(|(m1, m2, m3)| {
-                    Instruction::LessThan { m1, m2, m3 }
-                })
-            },
-            8 => {
-                Instruction::parse_three(abcde / 100).map(|(m1, m2, m3)| {
-                    Instruction::Equals { m1, m2, m3 }
-                })
-            },
-            9 => {
-                Instruction::parse_one(abcde / 100).map(|m1| Instruction::RelativeBaseAdjust { m1 })
+            99 => Ok(Instruction::Halt),
+            1 => modes(3).map(|m| Instruction::Add { m1: m[0], m2: m[1], m3: m[2] }),
+            2 => modes(3).map(|m| Instruction::Mult { m1: m[0], m2: m[1], m3: m[2] }),
+            3 => modes(1).map(|m| Instruction::Input { m1: m[0] }),
+            4 => modes(1).map(|m| Instruction::Output { m1: m[0] }),
+            5 => modes(2).map(|m| Instruction::JumpIfTrue { m1: m[0], m2: m[1] }),
+            6 => modes(2).map(|m| Instruction::JumpIfFalse { m1: m[0], m2: m[1] }),
+            7 => modes(3).map(|m| Instruction::LessThan { m1: m[0], m2: m[1], m3: m[2] }),
+            8 => modes(3).map(|m| Instruction::Equals { m1: m[0], m2: m[1], m3: m[2] }),
+            9 => modes(1).map(|m| Instruction::RelativeBaseAdjust { m1: m[0] }),
+            opcode => match dialect.and_then(|d| d.opcode(opcode)) {
+                Some(definition) => modes(definition.arity()).map(|modes| Instruction::Extension {
+                    opcode, mnemonic: definition.mnemonic(), modes, arity: definition.arity()
+                }),
+                None => Err(Error::UnknownOpcode { instruction_pointer, opcode: *abcde })
             }
-            _ => None
         }
     }
 
-    fn parse_one(abc: i64) -> Option<ParameterMode> {
-        ParameterMode::of(&abc.rem_euclid(10))
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Halt => "HLT",
+            Instruction::Add { .. } => "ADD",
+            Instruction::Mult { .. } => "MUL",
+            Instruction::Input { .. } => "IN",
+            Instruction::Output { .. } => "OUT",
+            Instruction::JumpIfTrue { .. } => "JT",
+            Instruction::JumpIfFalse { .. } => "JF",
+            Instruction::LessThan { .. } => "LT",
+            Instruction::Equals { .. } => "EQ",
+            Instruction::RelativeBaseAdjust { .. } => "ARB",
+            Instruction::Extension { mnemonic, .. } => mnemonic
+        }
     }
 
-    fn parse_two(abc: i64) -> Option<(ParameterMode, ParameterMode)> {
-        let c = abc.rem_euclid(10);
-        let b = (abc / 10).rem_euclid(10);
-        ParameterMode::of(&c).and_then(|m1| {
-            ParameterMode::of(&b).map(|m2| {
-                (m1, m2)
-            })
-        })
+    pub fn opcode(&self) -> i64 {
+        match self {
+            Instruction::Halt => 99,
+            Instruction::Add { .. } => 1,
+            Instruction::Mult { .. } => 2,
+            Instruction::Input { .. } => 3,
+            Instruction::Output { .. } => 4,
+            Instruction::JumpIfTrue { .. } => 5,
+            Instruction::JumpIfFalse { .. } => 6,
+            Instruction::LessThan { .. } => 7,
+            Instruction::Equals { .. } => 8,
+            Instruction::RelativeBaseAdjust { .. } => 9,
+            Instruction::Extension { opcode, .. } => *opcode
+        }
     }
 
-    fn parse_three(abc: i64) -> Option<(ParameterMode, ParameterMode, ParameterMode)> {
-        let c = abc.rem_euclid(10);
-        let b = (abc / 10).rem_euclid(10);
-        let a = (abc / 100).rem_euclid(10);
-        ParameterMode::of(&c).and_then(|m1| {
-            ParameterMode::of(&b).and_then(|m2| {
-                ParameterMode::of(&a).map(|m3| {
-                    (m1, m2, m3)
-                })
-            })
-        })
+    pub fn parameter_modes(&self) -> Vec<ParameterMode> {
+        match *self {
+            Instruction::Halt => vec!(),
+            Instruction::Input { m1 } | Instruction::Output { m1 } | Instruction::RelativeBaseAdjust { m1 } => vec!(m1),
+            Instruction::JumpIfTrue { m1, m2 } | Instruction::JumpIfFalse { m1, m2 } => vec!(m1, m2),
+            Instruction::Add { m1, m2, m3 } | Instruction::Mult { m1, m2, m3 } |
+            Instruction::LessThan { m1, m2, m3 } | Instruction::Equals { m1, m2, m3 } => vec!(m1, m2, m3),
+            Instruction::Extension { modes, arity, .. } => modes[..arity].to_vec()
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.parameter_modes().len()
+    }
+
+    // The canonical opcode word: no leading mode digits beyond the instruction's arity
+    pub fn encode(&self) -> i64 {
+        self.parameter_modes().iter().rev().fold(0, |acc, mode| 10 * acc + mode.digit()) * 100 + self.opcode()
     }
 }
 
-enum ParameterMode {
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParameterMode {
     Positional,
     Immediate,
-    Relative
+    Relative,
+    // A mode defined by the program's dialect, by its digit
+    Extension(i64)
 }
 
 impl ParameterMode {
@@ -363,8 +767,31 @@ impl ParameterMode {
             0 => Some(ParameterMode::Positional),
             1 => Some(ParameterMode::Immediate),
             2 => Some(ParameterMode::Relative),
-            _ => None            
+            _ => None
+        }
+    }
+
+    fn digit(&self) -> i64 {
+        match self {
+            ParameterMode::Positional => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+            ParameterMode::Extension(digit) => *digit
+        }
+    }
+
+    // Decodes the modes of the first `arity` parameters; the hundreds digit is the first parameter
+    fn parse_all(instruction_pointer: usize, abcde: i64, arity: usize, dialect: Option<&Dialect>) -> Result<[ParameterMode; 3], Error> {
+        let mut modes = [ParameterMode::Positional; 3];
+        let mut abc = abcde / 100;
+        for mode in modes.iter_mut().take(arity) {
+            let k = abc.rem_euclid(10);
+            *mode = ParameterMode::of(&k)
+                .or_else(|| dialect.and_then(|d| d.mode(k)).map(|_| ParameterMode::Extension(k)))
+                .ok_or(Error::BadParameterMode { instruction_pointer, opcode: abcde, mode: k })?;
+            abc /= 10;
         }
+        Ok(modes)
     }
 }
 
@@ -375,29 +802,29 @@ mod day02_tests {
     #[test]
     fn add_spec() {
         let mut program = Program::from_str("1,0,0,0,99");
-        assert_eq!(program.step(), State::Done);
+        assert_eq!(program.step(), Ok(State::Done));
         assert_eq!(program.memory[..], [2,0,0,0,99]);
         assert_eq!(program.instruction_pointer, 4);
 
-        assert_eq!(program.step(), State::Done);
+        assert_eq!(program.step(), Ok(State::Done));
     }
 
     #[test]
     fn multiply_spec() {
         let mut program = Program::from_str("2,3,0,3,99");
 
-        assert_eq!(program.step(), State::Done);
+        assert_eq!(program.step(), Ok(State::Done));
         assert_eq!(program.memory[..], [2,3,0,6,99]);
         assert_eq!(program.instruction_pointer, 4);
 
-        assert_eq!(program.step(), State::Done);
+        assert_eq!(program.step(), Ok(State::Done));
 
         let mut program = Program::from_str("2,4,4,5,99,0");
-        assert_eq!(program.step(), State::Done);
+        assert_eq!(program.step(), Ok(State::Done));
         assert_eq!(program.memory[..], [2,4,4,5,99,9801]);
         assert_eq!(program.instruction_pointer, 4);
 
-        assert_eq!(program.step(), State::Done);
+        assert_eq!(program.step(), Ok(State::Done));
     }
 }
 
@@ -410,29 +837,29 @@ mod day05_tests {
         let mut program = Program::from_str("3,9,8,9,10,9,4,9,99,-1,8");
 
         program.read_input(8);
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
 
-        assert_eq!(program.step(), State::Output(1));
+        assert_eq!(program.step(), Ok(State::Output(1)));
 
         let mut program = Program::from_str("3,9,8,9,10,9,4,9,99,-1,8");
         program.read_input(17);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(0)));
 
         let mut program = Program::from_str("3,3,1108,-1,8,3,4,3,99");
         program.read_input(8);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(1));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(1)));
 
         let mut program = Program::from_str("3,3,1108,-1,8,3,4,3,99");
         program.read_input(-17);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(0)));
     }
 
     #[test]
@@ -440,40 +867,40 @@ mod day05_tests {
         let code = "3,9,7,9,10,9,4,9,99,-1,8";
         let mut program = Program::from_str(code);
         program.read_input(-17);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(1));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(1)));
 
         let mut program = Program::from_str(code);
         program.read_input(8);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(0)));
 
         let mut program = Program::from_str(code);
         program.read_input(31);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(0)));
 
         let code = "3,3,1107,-1,8,3,4,3,99";
         let mut program = Program::from_str(code);
         program.read_input(-17);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(1));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(1)));
 
         let mut program = Program::from_str(code);
         program.read_input(8);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(0)));
 
         let mut program = Program::from_str(code);
         program.read_input(31);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Running);
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Ok(State::Output(0)));
     }
 
     #[test]
@@ -481,40 +908,40 @@ mod day05_tests {
         let code = "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9";
         let mut program = Program::from_str(code);
         program.read_input(0);
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 2);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 9);
 
-        assert_eq!(program.step(), State::Output(0));
+        assert_eq!(program.step(), Ok(State::Output(0)));
 
         let mut program = Program::from_str(code);
         program.read_input(-17);
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 2);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 5);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 9);
 
-        assert_eq!(program.step(), State::Output(1));
+        assert_eq!(program.step(), Ok(State::Output(1)));
         assert_eq!(program.instruction_pointer, 11);
 
         let mut program = Program::from_str(code);
         program.read_input(42);
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 2);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 5);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 9);
 
-        assert_eq!(program.step(), State::Output(1));
+        assert_eq!(program.step(), Ok(State::Output(1)));
         assert_eq!(program.instruction_pointer, 11);
     }
 
@@ -525,40 +952,40 @@ mod day05_tests {
         let input = 0;
         program.read_input(input);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 2);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 5);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 9);
 
-        assert_eq!(program.step(), State::Output((input != 0) as i64));
+        assert_eq!(program.step(), Ok(State::Output((input != 0) as i64)));
 
         let mut program = Program::from_str(code);
         let input = 17;
         program.read_input(input);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 2);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 9);
 
-        assert_eq!(program.step(), State::Output((input != 0) as i64));
+        assert_eq!(program.step(), Ok(State::Output((input != 0) as i64)));
 
         let mut program = Program::from_str(code);
         let input = -256;
         program.read_input(input);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 2);
 
-        assert_eq!(program.step(), State::Running);
+        assert_eq!(program.step(), Ok(State::Running));
         assert_eq!(program.instruction_pointer, 9);
 
-        assert_eq!(program.step(), State::Output((input != 0) as i64));
+        assert_eq!(program.step(), Ok(State::Output((input != 0) as i64)));
     }
 
     #[test]
@@ -568,8 +995,8 @@ mod day05_tests {
         let mut program = Program::from_str(code);
         program.read_input(-3);
         loop {
-            match program.step() {
-                State::Crashed | State::Done => panic!(),
+            match program.step().unwrap() {
+                State::Done => panic!(),
                 State::Output(x) => {
                     assert_eq!(x, 999);
                     break
@@ -582,8 +1009,8 @@ mod day05_tests {
         program.read_input(8);
 
         loop {
-            match program.step() {
-                State::Crashed | State::Done => panic!(),
+            match program.step().unwrap() {
+                State::Done => panic!(),
                 State::Output(x) => {
                     assert_eq!(x, 1000);
                     break
@@ -596,8 +1023,8 @@ mod day05_tests {
         program.read_input(88);
 
         loop {
-            match program.step() {
-                State::Crashed | State::Done => panic!(),
+            match program.step().unwrap() {
+                State::Done => panic!(),
                 State::Output(x) => {
                     assert_eq!(x, 1001);
                     break
@@ -614,7 +1041,7 @@ mod day05_tests {
         let program2 = program.clone();
 
         program.read_input(-1);
-        program.step();
+        program.step().unwrap();
 
         assert_ne!(program.instruction_pointer, program2.instruction_pointer);
     }
@@ -628,7 +1055,7 @@ mod relative_base_test {
     fn quine_test() {
         let mut program = Program::from_str("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99");
         let mut outputs = vec!();
-        program.run(&[], &mut |x| { outputs.push(x)});
+        program.run(&[], &mut |x| { outputs.push(x)}).unwrap();
 
         assert_eq!(outputs[..], [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99]);
     }
@@ -637,15 +1064,214 @@ mod relative_base_test {
     fn long_test() {
         let mut program = Program::from_str("1102,34915192,34915192,7,4,7,99,0");
         let mut out = 0;
-        program.run(&[], &mut |x| { out = x});
+        program.run(&[], &mut |x| { out = x}).unwrap();
 
         let out_str = format!("{}", out);
         assert_eq!(out_str.len(), 16);
 
         let mut program = Program::from_str("104,1125899906842624,99");
         out = 0;
-        program.run(&[], &mut |x| { out = x});
+        program.run(&[], &mut |x| { out = x}).unwrap();
         
         assert_eq!(out, 1125899906842624);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn unknown_opcode_test() {
+        let mut program = Program::from_str("1,0,0,0,42,99");
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Err(Error::UnknownOpcode { instruction_pointer: 4, opcode: 42 }));
+        assert_eq!(program.await_output(), Err(Error::UnknownOpcode { instruction_pointer: 4, opcode: 42 }));
+
+        let mut program = Program::from_str("1,0,0,0");
+        assert_eq!(program.await_output(), Err(Error::UnknownOpcode { instruction_pointer: 4, opcode: 0 }));
+    }
+
+    #[test]
+    fn bad_parameter_mode_test() {
+        let mut program = Program::from_str("1301,0,0,0,99");
+        assert_eq!(program.step(), Err(Error::BadParameterMode { instruction_pointer: 0, opcode: 1301, mode: 3 }));
+
+        let mut program = Program::from_str("30001,0,0,0,99");
+        assert_eq!(program.step(), Err(Error::BadParameterMode { instruction_pointer: 0, opcode: 30001, mode: 3 }));
+    }
+
+    #[test]
+    fn immediate_write_test() {
+        let mut program = Program::from_str("11101,1,1,1,99");
+        assert_eq!(program.step(), Err(Error::ImmediateWrite { instruction_pointer: 0, opcode: 11101 }));
+    }
+
+    #[test]
+    fn negative_address_test() {
+        let mut program = Program::from_str("1,-1,0,0,99");
+        assert_eq!(program.step(), Err(Error::NegativeAddress { instruction_pointer: 0, opcode: 1, address: -1 }));
+
+        let mut program = Program::from_str("109,-5,204,0,99");
+        assert_eq!(program.step(), Ok(State::Running));
+        assert_eq!(program.step(), Err(Error::NegativeAddress { instruction_pointer: 2, opcode: 204, address: -5 }));
+    }
+
+    #[test]
+    fn address_out_of_range_test() {
+        let mut program = Program::from_str("1101,1,2,1000000000,99");
+        assert_eq!(program.step(), Err(Error::AddressOutOfRange {
+            instruction_pointer: 0, opcode: 1101, address: 1000000000, max_address: memory::DEFAULT_MAX_ADDRESS
+        }));
+        assert_eq!(program.memory().len(), 5);
+
+        let mut program = Program::<memory::Paged>::parse("1101,1,2,1000000000,4,1000000000,99");
+        program.set_max_address(usize::MAX);
+        assert_eq!(program.await_output(), Ok(State::Output(3)));
+        assert_eq!(program.memory().page_count(), 2);
+        // the listings stop where the instruction cache does rather than making memory dense
+        assert_eq!(program.image().len(), MAX_CACHED_ADDRESS + 1);
+        assert_eq!(program.analyse().blocks.len(), 1);
+
+        let mut program = Program::from_str("4,100,99");
+        program.set_max_address(99);
+        assert_eq!(format!("{}", program.step().unwrap_err()), "address 100 is beyond the maximum address 99 (instruction 4 at 0)");
+    }
+
+    #[test]
+    fn invalid_jump_target_test() {
+        let mut program = Program::from_str("1105,1,-1");
+        assert_eq!(program.step(), Err(Error::InvalidJumpTarget { instruction_pointer: 0, opcode: 1105, target: -1 }));
+    }
+
+    #[test]
+    fn no_input_test() {
+        let mut program = Program::from_str("3,0,4,0,99");
+        assert_eq!(program.step(), Err(Error::NoInput { instruction_pointer: 0, opcode: 3 }));
+        assert_eq!(program.await_output(), Ok(State::AwaitingInput));
+
+        let mut program = Program::from_str("104,7,3,0,99");
+        let mut outputs = vec!();
+        assert_eq!(program.run(&[], |x| outputs.push(x)), Err(Error::NoInput { instruction_pointer: 2, opcode: 3 }));
+        assert_eq!(outputs[..], [7]);
+    }
+
+    #[test]
+    fn overflow_test() {
+        // squares 2^32, then adds the maximum i64 to -1
+        let source = "1002,9,4294967296,9,1101,9223372036854775807,-1,9,99,4294967296";
+        let mut program = Program::from_str(source);
+        assert_eq!(program.step(), Err(Error::Overflow { instruction_pointer: 0, opcode: 1002 }));
+        assert_eq!(program.peek(9), 4294967296);
+
+        let mut program = Program::from_str(source);
+        program.set_arithmetic(Arithmetic::Wrapping);
+        assert_eq!(program.run(&[], |_| ()), Ok(None));
+        assert_eq!(program.peek(9), i64::MAX - 1);
+
+        let mut program = Program::from_str("1101,9223372036854775807,1,0,99");
+        assert_eq!(program.step(), Err(Error::Overflow { instruction_pointer: 0, opcode: 1101 }));
+    }
+
+    #[test]
+    fn fuel_test() {
+        // counts upwards forever
+        let mut program = Program::from_str("104,0,1001,1,1,1,1105,1,0");
+        program.set_fuel(Some(10));
+        for expected in 0..4 {
+            assert_eq!(program.await_output(), Ok(State::Output(expected)));
+        }
+        assert_eq!(program.await_output(), Ok(State::OutOfFuel));
+        assert_eq!(program.fuel(), Some(0));
+        assert_eq!(program.instruction_pointer(), 2);
+
+        program.set_fuel(None);
+        assert_eq!(program.await_output(), Ok(State::Output(4)));
+
+        let mut program = Program::from_str("1105,1,0");
+        program.set_fuel(Some(1000));
+        assert_eq!(program.run(&[], |_| ()), Err(Error::OutOfFuel { instruction_pointer: 0, opcode: 1105 }));
+    }
+
+    #[test]
+    fn deadline_test() {
+        let mut program = Program::from_str("1105,1,0");
+        program.set_timeout(Duration::from_millis(20));
+        assert_eq!(program.await_output(), Ok(State::TimedOut));
+        assert_eq!(program.run(&[], |_| ()), Err(Error::TimedOut { instruction_pointer: 0, opcode: 1105 }));
+
+        let mut program = Program::from_str("104,1,99");
+        program.set_deadline(Some(Instant::now() + Duration::from_secs(60)));
+        assert_eq!(program.run(&[], |_| ()), Ok(Some(1)));
+    }
+
+    #[test]
+    fn display_test() {
+        let err = Error::NegativeAddress { instruction_pointer: 12, opcode: 1002, address: -3 };
+        assert_eq!(format!("{}", err), "negative address -3 (instruction 1002 at 12)");
+    }
+}
+
+#[cfg(test)]
+mod fork_tests {
+    use super::*;
+    use memory::CopyOnWrite;
+
+    #[test]
+    fn fork_test() {
+        // counts down from the input, outputting each value
+        let mut program = Program::<CopyOnWrite>::parse("3,11,4,11,1001,11,-1,11,1005,11,2,0,99");
+        program.read_input(3);
+        assert_eq!(program.await_output(), Ok(State::Output(3)));
+
+        let mut fork = program.fork();
+        assert_eq!(fork.state_hash(), program.state_hash());
+        assert!(fork.memory().dirty_pages().is_empty());
+        assert_eq!(fork.await_output(), Ok(State::Output(2)));
+        assert_ne!(fork.state_hash(), program.state_hash());
+        assert_eq!(fork.memory().dirty_pages().len(), 1);
+
+        assert_eq!(program.await_output(), Ok(State::Output(2)));
+        assert_eq!(fork.state_hash(), program.state_hash());
+
+        let dense = Program::<Vec<i64>>::from_words(program.memory().to_vec());
+        let mut dense = Program { instruction_pointer: program.instruction_pointer, ..dense };
+        assert_eq!(dense.state_hash(), program.state_hash());
+        dense.read_input(1);
+        assert_ne!(dense.state_hash(), program.state_hash());
+    }
+}
+
+#[cfg(test)]
+mod instruction_cache_tests {
+    use super::*;
+
+    #[test]
+    fn self_modifying_test() {
+        // overwrites the HLT at 4 with an OUT instruction
+        for &cached in [true, false].iter() {
+            let mut program = Program::from_str("1101,0,104,4,99,42,99");
+            program.set_instruction_cache(cached);
+            assert_eq!(program.await_output(), Ok(State::Output(42)));
+            assert_eq!(program.await_output(), Ok(State::Done));
+        }
+
+        let mut program = Program::from_str("99,5,99");
+        program.overwrite_memory(0, 104);
+        assert_eq!(program.await_output(), Ok(State::Output(5)));
+    }
+
+    #[test]
+    fn agreement_test() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let mut outputs = vec!();
+        for &cached in [true, false].iter() {
+            let mut program = Program::from_str(quine);
+            program.set_instruction_cache(cached);
+            let mut out = vec!();
+            program.run(&[], |x| out.push(x)).unwrap();
+            outputs.push(out);
+        }
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(outputs[0].len(), 16);
+    }
+}
@@ -0,0 +1,369 @@
+// Storage for a machine's memory. Every address reads as 0 until it is written.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Addresses above this are refused unless the machine is configured otherwise. Dense memory this
+// large takes 128 MiB.
+pub const DEFAULT_MAX_ADDRESS: usize = (1 << 24) - 1;
+
+pub trait Memory: Clone {
+    fn from_words(words: Vec<i64>) -> Self;
+
+    fn read(&self, address: usize) -> i64;
+
+    // Returns the value that was overwritten
+    fn write(&mut self, address: usize, value: i64) -> i64;
+
+    // One past the highest address that has been loaded or written
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Every word up to len; a sparse memory's len may be far too large for this, so its backend
+    // should override nonzero_words and hash_contents, which fall back on to_vec
+    fn to_vec(&self) -> Vec<i64> {
+        (0..self.len()).map(|address| self.read(address)).collect()
+    }
+
+    // The addresses and values of the words that are not zero, in order of address
+    fn nonzero_words(&self) -> Vec<(usize, i64)> {
+        self.to_vec().into_iter().enumerate().filter(|&(_, word)| word != 0).collect()
+    }
+
+    // A copy for exploring from the current state; see CopyOnWrite
+    fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    // Feeds the contents to the hasher page by page, skipping pages of zeros, so that memories
+    // reading the same at every address hash the same whatever their backend or length
+    fn hash_contents<H: Hasher>(&self, state: &mut H) {
+        for (page, words) in self.to_vec().chunks(PAGE_SIZE).enumerate() {
+            hash_page(page, page_digest(words), state);
+        }
+    }
+}
+
+// 0 for a page of zeros, which is left out of the hash, and never UNKNOWN_DIGEST
+fn page_digest(words: &[i64]) -> u64 {
+    match words.iter().rposition(|&word| word != 0) {
+        None => 0,
+        Some(last) => {
+            let mut hasher = DefaultHasher::new();
+            words[..=last].hash(&mut hasher);
+            hasher.finish().clamp(1, UNKNOWN_DIGEST - 1)
+        }
+    }
+}
+
+fn hash_page<H: Hasher>(page: usize, digest: u64, state: &mut H) {
+    if digest != 0 {
+        page.hash(state);
+        digest.hash(state);
+    }
+}
+
+// Dense memory grows to cover the highest address written
+impl Memory for Vec<i64> {
+    fn from_words(words: Vec<i64>) -> Vec<i64> {
+        words
+    }
+
+    fn read(&self, address: usize) -> i64 {
+        self.get(address).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, address: usize, value: i64) -> i64 {
+        if address >= Vec::len(self) {
+            self.resize(address + 1, 0);
+        }
+        std::mem::replace(&mut self[address], value)
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn to_vec(&self) -> Vec<i64> {
+        self.clone()
+    }
+
+    fn hash_contents<H: Hasher>(&self, state: &mut H) {
+        for (page, words) in self.chunks(PAGE_SIZE).enumerate() {
+            hash_page(page, page_digest(words), state);
+        }
+    }
+}
+
+const PAGE_SIZE: usize = 1024;
+
+// Sparse memory, allocated a page at a time as addresses are written
+#[derive(Clone, Default, Debug)]
+pub struct Paged {
+    pages: HashMap<usize, Box<[i64; PAGE_SIZE]>>,
+    len: usize
+}
+
+impl Paged {
+    pub fn new() -> Paged {
+        Paged::default()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl Memory for Paged {
+    fn from_words(words: Vec<i64>) -> Paged {
+        let mut memory = Paged::new();
+        for (address, word) in words.into_iter().enumerate() {
+            memory.write(address, word);
+        }
+        memory
+    }
+
+    fn read(&self, address: usize) -> i64 {
+        self.pages.get(&(address / PAGE_SIZE)).map(|page| page[address % PAGE_SIZE]).unwrap_or(0)
+    }
+
+    fn write(&mut self, address: usize, value: i64) -> i64 {
+        self.len = usize::max(self.len, address + 1);
+        let page = self.pages.entry(address / PAGE_SIZE).or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        std::mem::replace(&mut page[address % PAGE_SIZE], value)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn nonzero_words(&self) -> Vec<(usize, i64)> {
+        let mut words: Vec<(usize, i64)> = self.pages.iter()
+            .flat_map(|(page, words)| words.iter().enumerate().map(move |(offset, &word)| (page * PAGE_SIZE + offset, word)))
+            .filter(|&(_, word)| word != 0)
+            .collect();
+        words.sort_unstable_by_key(|&(address, _)| address);
+        words
+    }
+
+    fn hash_contents<H: Hasher>(&self, state: &mut H) {
+        let mut pages: Vec<(&usize, &Box<[i64; PAGE_SIZE]>)> = self.pages.iter().collect();
+        pages.sort_unstable_by_key(|&(page, _)| *page);
+        for (page, words) in pages {
+            hash_page(*page, page_digest(&words[..]), state);
+        }
+    }
+}
+
+const UNKNOWN_DIGEST: u64 = u64::MAX;
+
+#[derive(Debug)]
+struct Page {
+    words: [i64; PAGE_SIZE],
+    // Computed when first hashed, and forgotten on a write
+    digest: AtomicU64
+}
+
+impl Page {
+    fn zeroed() -> Page {
+        Page { words: [0; PAGE_SIZE], digest: AtomicU64::new(0) }
+    }
+
+    fn digest(&self) -> u64 {
+        match self.digest.load(Ordering::Relaxed) {
+            UNKNOWN_DIGEST => {
+                let digest = page_digest(&self.words);
+                self.digest.store(digest, Ordering::Relaxed);
+                digest
+            },
+            digest => digest
+        }
+    }
+}
+
+// A copy is only made to be written to
+impl Clone for Page {
+    fn clone(&self) -> Page {
+        Page { words: self.words, digest: AtomicU64::new(UNKNOWN_DIGEST) }
+    }
+}
+
+// Memory whose pages are shared between forks, so that forking a machine costs a page table
+// rather than its whole memory. A fork copies a page the first time it writes to it.
+#[derive(Clone, Default, Debug)]
+pub struct CopyOnWrite {
+    pages: Vec<Option<Arc<Page>>>,
+    len: usize,
+    // Pages written since this copy was forked
+    dirty: BTreeSet<usize>
+}
+
+impl CopyOnWrite {
+    pub fn new() -> CopyOnWrite {
+        CopyOnWrite::default()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.iter().flatten().count()
+    }
+
+    pub fn dirty_pages(&self) -> &BTreeSet<usize> {
+        &self.dirty
+    }
+}
+
+impl Memory for CopyOnWrite {
+    fn from_words(words: Vec<i64>) -> CopyOnWrite {
+        let pages = words.chunks(PAGE_SIZE).map(|words| {
+            let mut page = Page::zeroed();
+            page.words[..words.len()].copy_from_slice(words);
+            page.digest = AtomicU64::new(UNKNOWN_DIGEST);
+            Some(Arc::new(page))
+        }).collect();
+        CopyOnWrite { pages, len: words.len(), dirty: BTreeSet::new() }
+    }
+
+    fn read(&self, address: usize) -> i64 {
+        match self.pages.get(address / PAGE_SIZE) {
+            Some(Some(page)) => page.words[address % PAGE_SIZE],
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, address: usize, value: i64) -> i64 {
+        self.len = usize::max(self.len, address + 1);
+        let index = address / PAGE_SIZE;
+        if index >= self.pages.len() {
+            self.pages.resize(index + 1, None);
+        }
+        let page = Arc::make_mut(self.pages[index].get_or_insert_with(|| Arc::new(Page::zeroed())));
+        *page.digest.get_mut() = UNKNOWN_DIGEST;
+        self.dirty.insert(index);
+        std::mem::replace(&mut page.words[address % PAGE_SIZE], value)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn nonzero_words(&self) -> Vec<(usize, i64)> {
+        self.pages.iter().enumerate()
+            .filter_map(|(index, page)| page.as_ref().map(|page| (index, page)))
+            .flat_map(|(index, page)| page.words.iter().enumerate().map(move |(offset, &word)| (index * PAGE_SIZE + offset, word)))
+            .filter(|&(_, word)| word != 0)
+            .collect()
+    }
+
+    fn fork(&self) -> CopyOnWrite {
+        CopyOnWrite { pages: self.pages.clone(), len: self.len, dirty: BTreeSet::new() }
+    }
+
+    fn hash_contents<H: Hasher>(&self, state: &mut H) {
+        for (index, page) in self.pages.iter().enumerate() {
+            if let Some(page) = page {
+                hash_page(index, page.digest(), state);
+            }
+        }
+    }
+}
+
+// A vector shared between clones a page at a time, each page copied by the first clone to
+// change it. Holds the instruction cache, so that it is as cheap to fork as the memory.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedVec<T> {
+    pages: Vec<Arc<Vec<T>>>,
+    len: usize
+}
+
+impl<T: Clone> SharedVec<T> {
+    pub(crate) fn from_vec(items: Vec<T>) -> SharedVec<T> {
+        let len = items.len();
+        SharedVec { pages: items.chunks(PAGE_SIZE).map(|page| Arc::new(page.to_vec())).collect(), len }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        self.pages.get(index / PAGE_SIZE).and_then(|page| page.get(index % PAGE_SIZE))
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.pages.get_mut(index / PAGE_SIZE).and_then(|page| Arc::make_mut(page).get_mut(index % PAGE_SIZE))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Default for SharedVec<T> {
+    fn default() -> SharedVec<T> {
+        SharedVec { pages: vec!(), len: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paged_test() {
+        let mut memory = Paged::from_words(vec!(1, 2, 3));
+        assert_eq!(memory.page_count(), 1);
+        assert_eq!(memory.write(1 << 40, 7), 0);
+        assert_eq!(memory.write(1 << 40, 8), 7);
+        assert_eq!(memory.read(1 << 40), 8);
+        assert_eq!(memory.read(5000), 0);
+        assert_eq!(memory.page_count(), 2);
+        assert_eq!(memory.len(), (1 << 40) + 1);
+
+        let mut dense = vec!(1, 2, 3);
+        assert_eq!(dense.write(5, 9), 0);
+        assert_eq!(Memory::to_vec(&dense), [1, 2, 3, 0, 0, 9]);
+        assert_eq!(Paged::from_words(dense.clone()).to_vec(), dense);
+    }
+
+    #[test]
+    fn copy_on_write_test() {
+        let memory = CopyOnWrite::from_words((0..3000).collect());
+        assert_eq!(memory.page_count(), 3);
+        let mut fork = memory.fork();
+        assert_eq!(fork.write(1500, -1), 1500);
+        assert_eq!(fork.write(1 << 20, 7), 0);
+        assert_eq!(memory.read(1500), 1500);
+        assert_eq!(fork.read(1500), -1);
+        assert_eq!(fork.read(1 << 20), 7);
+        assert_eq!(fork.dirty_pages().iter().copied().collect::<Vec<usize>>(), [1, 1024]);
+        assert!(memory.dirty_pages().is_empty());
+        for page in [0, 2] {
+            assert!(Arc::ptr_eq(memory.pages[page].as_ref().unwrap(), fork.pages[page].as_ref().unwrap()));
+        }
+        assert!(!Arc::ptr_eq(memory.pages[1].as_ref().unwrap(), fork.pages[1].as_ref().unwrap()));
+    }
+
+    fn digest<M: Memory>(memory: &M) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        memory.hash_contents(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn hash_test() {
+        let words: Vec<i64> = vec!(1, 2, 3);
+        let mut longer = words.clone();
+        longer.write(5000, 0);
+        let mut sparse = CopyOnWrite::from_words(words.clone());
+        assert_eq!(digest(&sparse), digest(&words));
+        assert_eq!(digest(&longer), digest(&words));
+        assert_eq!(digest(&Paged::from_words(longer)), digest(&words));
+
+        sparse.write(2000, 4);
+        assert_ne!(digest(&sparse), digest(&words));
+        sparse.write(2000, 0);
+        assert_eq!(digest(&sparse), digest(&words));
+    }
+}
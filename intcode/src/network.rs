@@ -0,0 +1,423 @@
+// Running many intcode machines together.
+//
+// A `Cluster` wires machines output-to-input along fixed links (a pipeline, a ring or any graph),
+// like day 7's amplifiers. A `Network` instead routes addressed packets: each machine outputs
+// (destination, x, y) triples, and reads -1 whenever its queue is empty.
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::{Program, State};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Error {
+    pub node: usize,
+    pub error: crate::Error
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        write!(writer, "node {}: {}", self.node, self.error)
+    }
+}
+
+impl error::Error for Error {}
+
+// What a node's thread hands back: the machine, its outputs, and how it stopped
+type Finished = (Program, Vec<i64>, Result<(), crate::Error>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Schedule {
+    RoundRobin,
+    Threaded
+}
+
+pub struct Cluster {
+    nodes: Vec<Program>,
+    links: Vec<Vec<usize>>
+}
+
+impl Cluster {
+    pub fn new(nodes: Vec<Program>) -> Cluster {
+        let links = vec![vec!(); nodes.len()];
+        Cluster { nodes, links }
+    }
+
+    pub fn pipeline(nodes: Vec<Program>) -> Cluster {
+        let mut cluster = Cluster::new(nodes);
+        for i in 1..cluster.nodes.len() {
+            cluster.connect(i - 1, i);
+        }
+        cluster
+    }
+
+    pub fn ring(nodes: Vec<Program>) -> Cluster {
+        let mut cluster = Cluster::pipeline(nodes);
+        if !cluster.nodes.is_empty() {
+            cluster.connect(cluster.nodes.len() - 1, 0);
+        }
+        cluster
+    }
+
+    // Every output of `from` is also queued as input to `to`
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.links[from].push(to);
+    }
+
+    pub fn nodes(&self) -> &[Program] {
+        &self.nodes
+    }
+
+    pub fn node_mut(&mut self, idx: usize) -> &mut Program {
+        &mut self.nodes[idx]
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.nodes.iter().all(|node| node.is_terminated())
+    }
+
+    // Runs until every machine has halted or is starved of input, and returns each node's outputs.
+    // A threaded run cannot detect a cycle of machines waiting on one another, so only use it when
+    // every machine eventually halts.
+    pub fn run(&mut self, schedule: Schedule) -> Result<Vec<Vec<i64>>, Error> {
+        match schedule {
+            Schedule::RoundRobin => self.run_round_robin(),
+            Schedule::Threaded => self.run_threaded()
+        }
+    }
+
+    fn run_round_robin(&mut self) -> Result<Vec<Vec<i64>>, Error> {
+        let mut outputs = vec![vec!(); self.nodes.len()];
+        loop {
+            // Inputs only ever come from outputs, so a round without output changes nothing
+            let mut progress = false;
+            for (i, node_outputs) in outputs.iter_mut().enumerate() {
+                loop {
+                    match self.nodes[i].await_output().map_err(|error| Error { node: i, error })? {
+                        State::Output(x) | State::OutputAwaitingInput(x) => {
+                            progress = true;
+                            node_outputs.push(x);
+                            for &j in &self.links[i] {
+                                self.nodes[j].read_input(x);
+                            }
+                        },
+                        State::Running => continue,
+                        State::AwaitingInput | State::Done => break,
+                        state => return Err(Error { node: i, error: self.nodes[i].interrupted(&state) })
+                    }
+                }
+            }
+            if !progress {
+                return Ok(outputs)
+            }
+        }
+    }
+
+    fn run_threaded(&mut self) -> Result<Vec<Vec<i64>>, Error> {
+        let (senders, receivers): (Vec<mpsc::Sender<i64>>, Vec<mpsc::Receiver<i64>>) =
+            self.nodes.iter().map(|_| mpsc::channel()).unzip();
+        let handles: Vec<thread::JoinHandle<Finished>> =
+            self.nodes.drain(..).zip(receivers).zip(&self.links).map(|((mut program, receiver), links)| {
+                let targets: Vec<mpsc::Sender<i64>> = links.iter().map(|&j| senders[j].clone()).collect();
+                thread::spawn(move || {
+                    let mut outputs = vec!();
+                    let result = loop {
+                        match program.await_output() {
+                            Ok(State::Output(x)) | Ok(State::OutputAwaitingInput(x)) => {
+                                outputs.push(x);
+                                for target in &targets {
+                                    // a target that has already halted no longer needs input
+                                    target.send(x).ok();
+                                }
+                            },
+                            Ok(State::AwaitingInput) => match receiver.recv() {
+                                Ok(x) => program.read_input(x),
+                                Err(_) => break Ok(())
+                            },
+                            Ok(State::Running) => continue,
+                            Ok(State::Done) => break Ok(()),
+                            Ok(state) => break Err(program.interrupted(&state)),
+                            Err(e) => break Err(e)
+                        }
+                    };
+                    (program, outputs, result)
+                })
+            }).collect();
+        drop(senders);
+
+        let mut outputs = vec!();
+        let mut first_error = None;
+        for (node, handle) in handles.into_iter().enumerate() {
+            let (program, node_outputs, result) = handle.join().expect("intcode thread panicked");
+            if let (Err(error), None) = (result, &first_error) {
+                first_error = Some(Error { node, error });
+            }
+            self.nodes.push(program);
+            outputs.push(node_outputs);
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(outputs)
+        }
+    }
+}
+
+pub type Packet = (i64, i64);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Control {
+    Continue,
+    Stop
+}
+
+pub trait Monitor {
+    // Called for packets addressed outside the network
+    fn on_packet(&mut self, from: usize, destination: i64, packet: Packet) -> Control;
+
+    // Called when no machine has anything to do; returning None ends the run
+    fn on_idle(&mut self) -> Option<(usize, Packet)>;
+}
+
+// Day 23's NAT: remembers the last packet sent to address 255, and sends it to machine 0 whenever
+// the network goes idle. The run stops once it sends the same y value twice in a row.
+#[derive(Default)]
+pub struct Nat {
+    pub first: Option<Packet>,
+    pub last: Option<Packet>,
+    pub last_delivered: Option<Packet>
+}
+
+impl Nat {
+    pub const ADDRESS: i64 = 255;
+
+    pub fn new() -> Nat {
+        Nat::default()
+    }
+}
+
+impl Monitor for Nat {
+    fn on_packet(&mut self, _from: usize, destination: i64, packet: Packet) -> Control {
+        if destination == Nat::ADDRESS {
+            self.first = self.first.or(Some(packet));
+            self.last = Some(packet);
+        }
+        Control::Continue
+    }
+
+    fn on_idle(&mut self) -> Option<(usize, Packet)> {
+        let packet = self.last?;
+        if self.last_delivered.map(|(_, y)| y) == Some(packet.1) {
+            return None
+        }
+        self.last_delivered = Some(packet);
+        Some((0, packet))
+    }
+}
+
+pub struct Network {
+    nodes: Vec<Program>,
+    queues: Vec<VecDeque<Packet>>,
+    partial: Vec<Vec<i64>>
+}
+
+impl Network {
+    // Boots `size` copies of `program`, each given its address as the first input
+    pub fn new(program: &Program, size: usize) -> Network {
+        let nodes = (0..size).map(|address| {
+            let mut node = program.clone();
+            node.read_input(address as i64);
+            node
+        }).collect();
+        Network { nodes, queues: vec![VecDeque::new(); size], partial: vec![vec!(); size] }
+    }
+
+    pub fn nodes(&self) -> &[Program] {
+        &self.nodes
+    }
+
+    pub fn send(&mut self, address: usize, packet: Packet) {
+        self.queues[address].push_back(packet);
+    }
+
+    // Runs the machines round-robin, one packet (or a -1) per machine per turn, until the monitor
+    // stops the run
+    pub fn run<M: Monitor>(&mut self, monitor: &mut M) -> Result<(), Error> {
+        loop {
+            let mut idle = true;
+            for i in 0..self.nodes.len() {
+                match self.queues[i].pop_front() {
+                    Some((x, y)) => {
+                        idle = false;
+                        self.nodes[i].read_input(x);
+                        self.nodes[i].read_input(y);
+                    },
+                    None => self.nodes[i].read_input(-1)
+                }
+                loop {
+                    let out = match self.nodes[i].await_output().map_err(|error| Error { node: i, error })? {
+                        State::Output(x) | State::OutputAwaitingInput(x) => x,
+                        State::Running => continue,
+                        State::AwaitingInput | State::Done => break,
+                        state => return Err(Error { node: i, error: self.nodes[i].interrupted(&state) })
+                    };
+                    idle = false;
+                    self.partial[i].push(out);
+                    if let [destination, x, y] = self.partial[i][..] {
+                        self.partial[i].clear();
+                        if destination >= 0 && (destination as usize) < self.nodes.len() {
+                            self.queues[destination as usize].push_back((x, y));
+                        } else if monitor.on_packet(i, destination, (x, y)) == Control::Stop {
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+            if idle {
+                match monitor.on_idle() {
+                    Some((address, packet)) => self.send(address, packet),
+                    None => return Ok(())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads two inputs (phase, signal) and outputs 10 * signal + phase
+    const AMP: &str = "3,15,3,16,1002,16,10,16,1,15,16,16,4,16,99,0,0";
+
+    fn amps(phases: &[i64]) -> Vec<Program> {
+        phases.iter().map(|&phase| {
+            let mut amp = Program::from_str(AMP);
+            amp.read_input(phase);
+            amp
+        }).collect()
+    }
+
+    #[test]
+    fn pipeline_test() {
+        for schedule in [Schedule::RoundRobin, Schedule::Threaded].iter() {
+            let mut cluster = Cluster::pipeline(amps(&[1, 2, 3]));
+            cluster.node_mut(0).read_input(0);
+            let outputs = cluster.run(*schedule).unwrap();
+            assert_eq!(outputs, [vec!(1), vec!(12), vec!(123)]);
+            assert!(cluster.is_halted());
+        }
+    }
+
+    #[test]
+    fn ring_test() {
+        let code = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+        for schedule in [Schedule::RoundRobin, Schedule::Threaded].iter() {
+            let nodes = [9, 8, 7, 6, 5].iter().map(|&phase| {
+                let mut amp = Program::from_str(code);
+                amp.read_input(phase);
+                amp
+            }).collect();
+            let mut cluster = Cluster::ring(nodes);
+            cluster.node_mut(0).read_input(0);
+            let outputs = cluster.run(*schedule).unwrap();
+            assert_eq!(outputs[4].last(), Some(&139629729));
+        }
+    }
+
+    #[test]
+    fn graph_test() {
+        // one producer fanned out to two amplifiers
+        let mut cluster = Cluster::new(amps(&[1, 2, 3]));
+        cluster.connect(0, 1);
+        cluster.connect(0, 2);
+        cluster.node_mut(0).read_input(5);
+        assert_eq!(cluster.run(Schedule::RoundRobin).unwrap(), [vec!(51), vec!(512), vec!(513)]);
+    }
+
+    #[test]
+    fn starved_test() {
+        let mut cluster = Cluster::pipeline(amps(&[1, 2]));
+        assert_eq!(cluster.run(Schedule::RoundRobin).unwrap(), [vec!(), vec!()]);
+        assert!(!cluster.is_halted());
+    }
+
+    #[test]
+    fn error_test() {
+        let mut cluster = Cluster::pipeline(vec!(Program::from_str("104,1,99"), Program::from_str("3,0,42")));
+        let err = Error { node: 1, error: crate::Error::UnknownOpcode { instruction_pointer: 2, opcode: 42 } };
+        assert_eq!(cluster.run(Schedule::RoundRobin), Err(err.clone()));
+
+        let mut cluster = Cluster::pipeline(vec!(Program::from_str("104,1,99"), Program::from_str("3,0,42")));
+        assert_eq!(cluster.run(Schedule::Threaded), Err(err));
+    }
+
+    // Forwards every packet it receives unchanged to the next address, or to 255 from address 2
+    const RELAY: &str = "
+        .var addr 0
+        .var x 1
+        .var y 2
+        .var tmp 3
+        .var dest 4
+                ARB #frame
+                IN addr
+        wait:   IN x
+                EQ x, #-1, tmp
+                JT tmp, #wait
+                IN y
+                EQ addr, #2, tmp
+                JF tmp, #next
+                ADD #255, #0, dest
+                JT #1, #send
+        next:   ADD addr, #1, dest
+        send:   OUT dest
+                OUT x
+                OUT y
+                JT #1, #wait
+        frame:  DB 0, 0, 0, 0, 0
+    ";
+
+    struct Recorder {
+        packets: Vec<(usize, i64, Packet)>
+    }
+
+    impl Monitor for Recorder {
+        fn on_packet(&mut self, from: usize, destination: i64, packet: Packet) -> Control {
+            self.packets.push((from, destination, packet));
+            Control::Stop
+        }
+
+        fn on_idle(&mut self) -> Option<(usize, Packet)> {
+            None
+        }
+    }
+
+    #[test]
+    fn network_test() {
+        let program = Program::assemble(RELAY).unwrap();
+        let mut network = Network::new(&program, 3);
+        network.send(0, (7, 42));
+        let mut recorder = Recorder { packets: vec!() };
+        network.run(&mut recorder).unwrap();
+        assert_eq!(recorder.packets, [(2, 255, (7, 42))]);
+
+        // without traffic the network goes idle straight away
+        let mut network = Network::new(&program, 3);
+        let mut recorder = Recorder { packets: vec!() };
+        network.run(&mut recorder).unwrap();
+        assert!(recorder.packets.is_empty());
+    }
+
+    #[test]
+    fn nat_test() {
+        let program = Program::assemble(RELAY).unwrap();
+        let mut network = Network::new(&program, 3);
+        network.send(1, (3, 17));
+        let mut nat = Nat::new();
+        network.run(&mut nat).unwrap();
+        assert_eq!(nat.first, Some((3, 17)));
+        assert_eq!(nat.last_delivered, Some((3, 17)));
+    }
+}
@@ -0,0 +1,250 @@
+// Counts where a machine spends its steps. Attach a Profiler as the observer of a run; it counts
+// executions by address, by opcode and by basic block, remembers the highest relative base, and
+// attributes every step to a stack of the functions recovered by static analysis.
+//
+// A jump to a function's entry is a call, made with the relative base it had then. A taken jump
+// through a relative-mode target, once the relative base is back to that value, is its return.
+// The stacks can be written in the folded format read by flamegraph.pl and inferno:
+//
+//   main;fn@1020;fn@1144 5208
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::analysis::Analysis;
+use crate::dialect::Dialect;
+use crate::disasm;
+use crate::memory::Memory;
+use crate::trace::{Event, Observer};
+use crate::{Instruction, ParameterMode, Program};
+
+struct Frame {
+    entry: usize,
+    relative_base: i64
+}
+
+pub struct Profiler {
+    memory: Vec<i64>,
+    dialect: Option<Arc<Dialect>>,
+    entries: BTreeSet<usize>,
+    // The start of the block containing each address, for the addresses in blocks
+    blocks: HashMap<usize, usize>,
+    steps: u64,
+    by_address: HashMap<usize, u64>,
+    by_opcode: BTreeMap<&'static str, u64>,
+    relative_base: i64,
+    deepest: (i64, usize),
+    stack: Vec<Frame>,
+    // Steps per call stack, by function entry; the current stack's steps are counted separately
+    // until it changes
+    stacks: HashMap<Vec<usize>, u64>,
+    current: u64
+}
+
+impl Profiler {
+    pub fn new<M: Memory>(program: &Program<M>) -> Profiler {
+        Profiler::with_analysis(program, &program.analyse())
+    }
+
+    pub fn with_analysis<M: Memory>(program: &Program<M>, analysis: &Analysis) -> Profiler {
+        let blocks = analysis.blocks.values()
+            .flat_map(|block| (block.start..block.end()).map(move |address| (address, block.start)))
+            .collect();
+        Profiler {
+            memory: program.image(),
+            dialect: program.dialect.clone(),
+            entries: analysis.functions.iter().map(|f| f.entry).collect(),
+            blocks,
+            steps: 0,
+            by_address: HashMap::new(),
+            by_opcode: BTreeMap::new(),
+            relative_base: program.relative_base,
+            deepest: (program.relative_base, program.instruction_pointer),
+            stack: vec!(),
+            stacks: HashMap::new(),
+            current: 0
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn executions(&self, address: usize) -> u64 {
+        self.by_address.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn opcode_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.by_opcode
+    }
+
+    // The highest relative base reached, and the instruction that set it
+    pub fn deepest_relative_base(&self) -> (i64, usize) {
+        self.deepest
+    }
+
+    fn switch_stack(&mut self) {
+        if self.current > 0 {
+            let key = self.stack.iter().map(|frame| frame.entry).collect();
+            *self.stacks.entry(key).or_insert(0) += self.current;
+            self.current = 0;
+        }
+    }
+
+    // Steps for each call stack seen, outermost function first
+    pub fn stacks(&self) -> Vec<(Vec<usize>, u64)> {
+        let mut stacks: HashMap<Vec<usize>, u64> = self.stacks.clone();
+        if self.current > 0 {
+            *stacks.entry(self.stack.iter().map(|frame| frame.entry).collect()).or_insert(0) += self.current;
+        }
+        let mut stacks: Vec<(Vec<usize>, u64)> = stacks.into_iter().collect();
+        stacks.sort_unstable();
+        stacks
+    }
+
+    pub fn folded(&self) -> String {
+        self.stacks().iter().map(|(stack, steps)| {
+            let names: Vec<String> = stack.iter().map(|entry| format!("fn@{}", entry)).collect();
+            let mut line = vec!("main".to_string());
+            line.extend(names);
+            format!("{} {}\n", line.join(";"), steps)
+        }).collect()
+    }
+
+    // Steps in each function, and in it or anything it called, by entry; main is None
+    pub fn functions(&self) -> Vec<(Option<usize>, u64, u64)> {
+        let mut totals: BTreeMap<Option<usize>, (u64, u64)> = BTreeMap::new();
+        for (stack, steps) in self.stacks() {
+            totals.entry(stack.last().copied()).or_insert((0, 0)).0 += steps;
+            let callers: BTreeSet<Option<usize>> = Some(None).into_iter().chain(stack.iter().map(|&entry| Some(entry))).collect();
+            for function in callers {
+                totals.entry(function).or_insert((0, 0)).1 += steps;
+            }
+        }
+        let mut functions: Vec<(Option<usize>, u64, u64)> = totals.into_iter().map(|(f, (own, total))| (f, own, total)).collect();
+        functions.sort_by_key(|&(_, own, total)| (Reverse(total), Reverse(own)));
+        functions
+    }
+
+    // A text report listing the `top` busiest addresses, blocks and functions
+    pub fn report(&self, top: usize) -> String {
+        let percent = |n: u64| 100.0 * n as f64 / self.steps.max(1) as f64;
+        let mut report = vec!(format!("{} steps", self.steps));
+        report.push(format!("deepest relative base {} (set at {})", self.deepest.0, self.deepest.1));
+
+        report.push("\nopcodes:".to_string());
+        let mut opcodes: Vec<(&&str, &u64)> = self.by_opcode.iter().collect();
+        opcodes.sort_by_key(|&(_, n)| Reverse(*n));
+        for (mnemonic, n) in opcodes {
+            report.push(format!("{:>12} {:>6.2}%  {}", n, percent(*n), mnemonic));
+        }
+
+        report.push("\naddresses:".to_string());
+        let mut addresses: Vec<(&usize, &u64)> = self.by_address.iter().collect();
+        addresses.sort_by_key(|&(address, n)| (Reverse(*n), *address));
+        for (&address, &n) in addresses.into_iter().take(top) {
+            let text = if address < self.memory.len() { disasm::decode_in(&self.memory, address, self.dialect.as_deref()).text() } else { String::new() };
+            report.push(format!("{:>12} {:>6.2}%  {:>5}: {}", n, percent(n), address, text));
+        }
+
+        report.push("\nblocks:".to_string());
+        let mut blocks: HashMap<usize, u64> = HashMap::new();
+        for (address, n) in &self.by_address {
+            if let Some(&start) = self.blocks.get(address) {
+                *blocks.entry(start).or_insert(0) += n;
+            }
+        }
+        let mut blocks: Vec<(usize, u64)> = blocks.into_iter().collect();
+        blocks.sort_by_key(|&(start, n)| (Reverse(n), start));
+        for (start, n) in blocks.into_iter().take(top) {
+            report.push(format!("{:>12} {:>6.2}%  block {}", n, percent(n), start));
+        }
+
+        report.push("\nfunctions (self, total):".to_string());
+        for (function, own, total) in self.functions().into_iter().take(top) {
+            let name = function.map(|entry| format!("fn@{}", entry)).unwrap_or_else(|| "main".to_string());
+            report.push(format!("{:>12} {:>12} {:>6.2}%  {}", own, total, percent(total), name));
+        }
+        report.join("\n")
+    }
+}
+
+impl Observer for Profiler {
+    fn on_step(&mut self, event: &Event) {
+        self.steps += 1;
+        self.current += 1;
+        *self.by_address.entry(event.address).or_insert(0) += 1;
+        *self.by_opcode.entry(event.instruction.mnemonic()).or_insert(0) += 1;
+        if let Some((_, new)) = event.relative_base {
+            self.relative_base = new;
+            if new > self.deepest.0 {
+                self.deepest = (new, event.address);
+            }
+        }
+
+        let jumped = match event.instruction {
+            Instruction::JumpIfTrue { m2, .. } | Instruction::JumpIfFalse { m2, .. } if event.next != event.address + 3 => Some(m2),
+            _ => None
+        };
+        let returned = jumped == Some(ParameterMode::Relative) &&
+            self.stack.last().map(|frame| frame.relative_base == self.relative_base).unwrap_or(false);
+        if returned {
+            self.switch_stack();
+            self.stack.pop();
+        } else if jumped.is_some() && self.entries.contains(&event.next) {
+            self.switch_stack();
+            self.stack.push(Frame { entry: event.next, relative_base: self.relative_base });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // main calls double twice, and double calls inc
+    const CALLS: &str = "
+                ARB #stack
+                ADD #ret1, #0, rb+0
+                JT #1, #double
+        ret1:   ADD #ret2, #0, rb+0
+                JT #1, #double
+        ret2:   OUT [x]
+                HLT
+        double: ARB #2
+                MUL [x], #2, [x]
+                ADD #back, #0, rb+0
+                JT #1, #inc
+        back:   ARB #-2
+                JF #0, rb+0
+        inc:    ARB #1
+                ADD [x], #1, [x]
+                ARB #-1
+                JF #0, rb+0
+        x:      DB 5
+        stack:  DB 0, 0, 0, 0, 0, 0
+    ";
+
+    #[test]
+    fn profile_test() {
+        let mut program = Program::assemble(CALLS).unwrap();
+        let mut profiler = Profiler::new(&program);
+        assert_eq!(program.run_observed(&[], |_| (), &mut profiler), Ok(Some(23)));
+
+        assert_eq!(profiler.steps(), 27);
+        assert_eq!(profiler.executions(0), 1);
+        assert_eq!(profiler.executions(21), 2);
+        assert_eq!(profiler.opcode_counts()["ARB"], 9);
+        assert_eq!(profiler.deepest_relative_base(), (52, 37));
+
+        assert_eq!(profiler.stacks(), [(vec!(), 7), (vec!(19), 12), (vec!(19, 37), 8)]);
+        assert_eq!(profiler.folded(), "main 7\nmain;fn@19 12\nmain;fn@19;fn@37 8\n");
+        assert_eq!(profiler.functions(), [(None, 7, 27), (Some(19), 12, 20), (Some(37), 8, 8)]);
+
+        let report = profiler.report(3);
+        assert!(report.starts_with("27 steps\ndeepest relative base 52 (set at 37)\n"));
+        assert!(report.contains("\n           9  33.33%  ARB\n"));
+        assert!(report.contains("\n           2   7.41%     21: MUL [48], #2, [48]\n"));
+    }
+}
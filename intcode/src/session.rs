@@ -0,0 +1,295 @@
+// Recording a machine's I/O, and replaying it to check the machine still behaves the same. A
+// session is saved as a versioned text file with one line per input consumed or output produced,
+// numbered by the step it happened on:
+//
+//   intcode-session 1
+//   input 0 5
+//   output 2 10
+//   steps 3
+//
+// The final line gives how many steps were recorded.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::memory::Memory;
+use crate::trace::{Event, Observer};
+use crate::Program;
+
+const MAGIC: &str = "intcode-session";
+const VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Record {
+    Input(i64),
+    Output(i64)
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Record::Input(x) => write!(writer, "input {}", x),
+            Record::Output(x) => write!(writer, "output {}", x)
+        }
+    }
+}
+
+fn records(step: u64, event: &Event) -> Vec<(u64, Record)> {
+    let mut records = vec!();
+    if let Some(x) = event.input {
+        records.push((step, Record::Input(x)));
+    }
+    if let Some(x) = event.output {
+        records.push((step, Record::Output(x)));
+    }
+    records
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Session {
+    pub records: Vec<(u64, Record)>,
+    pub steps: u64
+}
+
+// Observes a machine and builds up a session
+#[derive(Default)]
+pub struct Recorder {
+    session: Session
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    pub fn finish(self) -> Session {
+        self.session
+    }
+}
+
+impl Observer for Recorder {
+    fn on_step(&mut self, event: &Event) {
+        self.session.records.extend(records(self.session.steps, event));
+        self.session.steps += 1;
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NotASession,
+    UnsupportedVersion(u32),
+    BadLine(usize)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(writer, "{}", e),
+            Error::NotASession => write!(writer, "not an intcode session"),
+            Error::UnsupportedVersion(v) => write!(writer, "unsupported session version {}", v),
+            Error::BadLine(line) => write!(writer, "cannot parse line {}", line)
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// Where a replay first differed from the recording
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Divergence {
+    // The machine did something at `step` other than what was recorded next
+    Unexpected { step: u64, expected: Option<(u64, Record)>, found: Record },
+    // The recorded steps ran out before this record was reproduced
+    Missing { expected: (u64, Record) },
+    Crashed { step: u64, error: crate::Error }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Divergence::Unexpected { step, expected: Some((at, record)), found } =>
+                write!(writer, "step {}: expected {} at step {}, found {}", step, record, at, found),
+            Divergence::Unexpected { step, expected: None, found } =>
+                write!(writer, "step {}: unexpected {}", step, found),
+            Divergence::Missing { expected: (at, record) } => write!(writer, "{} at step {} never happened", record, at),
+            Divergence::Crashed { step, error } => write!(writer, "step {}: {}", step, error)
+        }
+    }
+}
+
+impl error::Error for Divergence {}
+
+impl Session {
+    pub fn inputs(&self) -> Vec<i64> {
+        self.records.iter().filter_map(|(_, record)| match record {
+            Record::Input(x) => Some(*x),
+            Record::Output(_) => None
+        }).collect()
+    }
+
+    pub fn outputs(&self) -> Vec<i64> {
+        self.records.iter().filter_map(|(_, record)| match record {
+            Record::Output(x) => Some(*x),
+            Record::Input(_) => None
+        }).collect()
+    }
+
+    // Queues the recorded inputs on a machine in the state the recording started from, and runs it
+    // for the recorded number of steps, checking each input and output against the recording
+    pub fn replay<M: Memory>(&self, program: &mut Program<M>) -> Result<(), Divergence> {
+        for x in self.inputs() {
+            program.read_input(x);
+        }
+        let mut expected = self.records.iter().copied();
+        let mut divergence = None;
+        for step in 0..self.steps {
+            let mut check = |event: &Event| {
+                for (at, found) in records(step, event) {
+                    match expected.next() {
+                        Some(record) if record == (at, found) => (),
+                        other => if divergence.is_none() {
+                            divergence = Some(Divergence::Unexpected { step, expected: other, found });
+                        }
+                    }
+                }
+            };
+            if let Err(error) = program.step_observed(&mut check) {
+                return Err(Divergence::Crashed { step, error })
+            }
+            if let Some(divergence) = divergence {
+                return Err(divergence)
+            }
+        }
+        match expected.next() {
+            Some(record) => Err(Divergence::Missing { expected: record }),
+            None => Ok(())
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{} {}", MAGIC, VERSION)?;
+        for (step, record) in &self.records {
+            match record {
+                Record::Input(x) => writeln!(writer, "input {} {}", step, x)?,
+                Record::Output(x) => writeln!(writer, "output {} {}", step, x)?
+            }
+        }
+        writeln!(writer, "steps {}", self.steps)?;
+        writer.flush()
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Session, Error> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or(Error::NotASession)??;
+        match header.split_whitespace().collect::<Vec<&str>>()[..] {
+            [MAGIC, version] => match version.parse::<u32>() {
+                Ok(VERSION) => (),
+                Ok(other) => return Err(Error::UnsupportedVersion(other)),
+                Err(_) => return Err(Error::NotASession)
+            },
+            _ => return Err(Error::NotASession)
+        }
+
+        let mut session = Session::default();
+        for (idx, line) in lines.enumerate() {
+            let line = line?;
+            let bad = || Error::BadLine(idx + 2);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let step = || fields.get(1).and_then(|s| s.parse::<u64>().ok()).ok_or_else(bad);
+            let value = || fields.get(2).and_then(|s| s.parse::<i64>().ok()).ok_or_else(bad);
+            match fields.first() {
+                None => continue,
+                Some(&"input") => session.records.push((step()?, Record::Input(value()?))),
+                Some(&"output") => session.records.push((step()?, Record::Output(value()?))),
+                Some(&"steps") => session.steps = step()?,
+                Some(_) => return Err(bad())
+            }
+        }
+        Ok(session)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(io::BufWriter::new(fs::File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session, Error> {
+        Session::read_from(io::BufReader::new(fs::File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    // 0: IN [9]; 2: MUL [9], #2, [9]; 6: OUT [9]; then back to 0
+    const DOUBLER: &str = "3,9,1002,9,2,9,4,9,1105,1,0";
+
+    fn record(inputs: &[i64]) -> Session {
+        let mut program = Program::from_str(DOUBLER);
+        let mut recorder = Recorder::new();
+        for &x in inputs {
+            program.read_input(x);
+            assert!(matches!(program.await_output_observed(&mut recorder), Ok(State::Output(_))));
+        }
+        recorder.finish()
+    }
+
+    #[test]
+    fn record_test() {
+        let session = record(&[5, 7]);
+        assert_eq!(session.records, [(0, Record::Input(5)), (2, Record::Output(10)), (4, Record::Input(7)), (6, Record::Output(14))]);
+        assert_eq!(session.steps, 7);
+
+        let mut file = vec!();
+        session.write_to(&mut file).unwrap();
+        assert_eq!(String::from_utf8(file.clone()).unwrap(), "\
+intcode-session 1
+input 0 5
+output 2 10
+input 4 7
+output 6 14
+steps 7
+");
+        assert_eq!(Session::read_from(&file[..]).unwrap(), session);
+    }
+
+    #[test]
+    fn replay_test() {
+        let session = record(&[5, 7]);
+        assert_eq!(session.replay(&mut Program::from_str(DOUBLER)), Ok(()));
+
+        // an interpreter that tripled instead would be caught
+        let drifted = "3,9,1002,9,3,9,4,9,1105,1,0";
+        assert_eq!(session.replay(&mut Program::from_str(drifted)), Err(Divergence::Unexpected {
+            step: 2, expected: Some((2, Record::Output(10))), found: Record::Output(15)
+        }));
+
+        let slow = "3,15,1002,15,2,15,1101,0,0,16,4,15,1105,1,0,0,0";
+        let err = session.replay(&mut Program::from_str(slow)).unwrap_err();
+        assert_eq!(err.to_string(), "step 3: expected output 10 at step 2, found output 10");
+
+        let mut short = session.clone();
+        short.steps = 5;
+        assert_eq!(short.replay(&mut Program::from_str(DOUBLER)), Err(Divergence::Missing { expected: (6, Record::Output(14)) }));
+    }
+
+    #[test]
+    fn error_test() {
+        let read = |s: &str| Session::read_from(s.as_bytes()).map(|_| ()).map_err(|e| e.to_string());
+        assert_eq!(read("intcode-snapshot 1"), Err("not an intcode session".to_string()));
+        assert_eq!(read("intcode-session 2"), Err("unsupported session version 2".to_string()));
+        assert_eq!(read("intcode-session 1\ninput 3"), Err("cannot parse line 2".to_string()));
+    }
+}
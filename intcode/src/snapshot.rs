@@ -0,0 +1,305 @@
+// Machine state on disk, as a versioned text file:
+//
+//   intcode-snapshot 2
+//   instruction_pointer 12
+//   relative_base 0
+//   return_code none
+//   input 1,2
+//   memory 1002,4,3,4,33
+//
+// Fields may come in any order, but every one of them is required. A memory mostly of zeros, as a
+// sparse one written far out is, is saved as its length and its nonzero words instead of `memory`:
+//
+//   length 1099511627777
+//   words 0:1002,1:4,2:3,3:4,4:33,1099511627776:7
+//
+// which version 1 did not have. A memory reaching beyond the maximum address it is restored with,
+// DEFAULT_MAX_ADDRESS unless restore_with_max_address says otherwise, is refused.
+//
+// The maximum address, fuel, deadline and arithmetic setting are configuration rather than
+// machine state, and are not saved.
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::Program;
+use crate::memory::{Memory, DEFAULT_MAX_ADDRESS};
+
+const MAGIC: &str = "intcode-snapshot";
+const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    MissingField(&'static str),
+    BadField { line: usize, field: String }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(writer, "{}", e),
+            Error::NotASnapshot => write!(writer, "not an intcode snapshot"),
+            Error::UnsupportedVersion(v) => write!(writer, "unsupported snapshot version {}", v),
+            Error::MissingField(field) => write!(writer, "snapshot has no {} field", field),
+            Error::BadField { line, field } => write!(writer, "cannot parse field {} on line {}", field, line)
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+fn join(words: &[i64]) -> String {
+    words.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn split(line: &str) -> Option<Vec<i64>> {
+    if line.is_empty() {
+        return Some(vec!())
+    }
+    line.split(',').map(|s| s.trim().parse().ok()).collect()
+}
+
+fn split_words(line: &str) -> Option<Vec<(usize, i64)>> {
+    if line.is_empty() {
+        return Some(vec!())
+    }
+    line.split(',').map(|s| {
+        let (address, word) = s.split_once(':')?;
+        Some((address.trim().parse().ok()?, word.trim().parse().ok()?))
+    }).collect()
+}
+
+impl<M: Memory> Program<M> {
+    pub fn write_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let input: Vec<i64> = self.input_buffer.iter().copied().collect();
+        writeln!(writer, "{} {}", MAGIC, VERSION)?;
+        writeln!(writer, "instruction_pointer {}", self.instruction_pointer)?;
+        writeln!(writer, "relative_base {}", self.relative_base)?;
+        match self.return_code {
+            Some(code) => writeln!(writer, "return_code {}", code)?,
+            None => writeln!(writer, "return_code none")?
+        }
+        writeln!(writer, "input {}", join(&input))?;
+        let words = self.memory.nonzero_words();
+        // dense unless fewer than a quarter of the words are nonzero
+        if 4 * words.len() >= self.memory.len() {
+            writeln!(writer, "memory {}", join(&self.memory.to_vec()))?;
+        } else {
+            writeln!(writer, "length {}", self.memory.len())?;
+            let words: Vec<String> = words.iter().map(|(address, word)| format!("{}:{}", address, word)).collect();
+            writeln!(writer, "words {}", words.join(","))?;
+        }
+        writer.flush()
+    }
+
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_snapshot(io::BufWriter::new(fs::File::create(path)?))
+    }
+}
+
+impl Program {
+    pub fn read_snapshot<R: BufRead>(reader: R) -> Result<Program, Error> {
+        Program::restore(reader)
+    }
+
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> Result<Program, Error> {
+        Program::read_snapshot(io::BufReader::new(fs::File::open(path)?))
+    }
+}
+
+impl<M: Memory> Program<M> {
+    // Like read_snapshot, for any memory backend: `Program::<Paged>::restore(reader)`
+    pub fn restore<R: BufRead>(reader: R) -> Result<Program<M>, Error> {
+        Program::restore_with_max_address(reader, DEFAULT_MAX_ADDRESS)
+    }
+
+    // Restores a program whose maximum address is `max_address`; a sparse memory reaching beyond
+    // it is a bad field rather than an attempt to allocate it
+    pub fn restore_with_max_address<R: BufRead>(reader: R, max_address: usize) -> Result<Program<M>, Error> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or(Error::NotASnapshot)??;
+        match header.split_whitespace().collect::<Vec<&str>>()[..] {
+            [MAGIC, version] => match version.parse::<u32>() {
+                Ok(1..=VERSION) => (),
+                Ok(other) => return Err(Error::UnsupportedVersion(other)),
+                Err(_) => return Err(Error::NotASnapshot)
+            },
+            _ => return Err(Error::NotASnapshot)
+        }
+
+        let mut instruction_pointer = None;
+        let mut relative_base = None;
+        let mut return_code = None;
+        let mut input_buffer = None;
+        let mut memory = None;
+        let mut length = None;
+        let mut words = None;
+        for (idx, line) in lines.enumerate() {
+            let line = line?;
+            let line_number = idx + 2;
+            let (field, value) = match line.find(' ') {
+                Some(k) => (&line[..k], line[k + 1..].trim()),
+                None => (line.trim(), "")
+            };
+            let bad = || Error::BadField { line: line_number, field: field.to_string() };
+            match field {
+                "" => continue,
+                "instruction_pointer" => instruction_pointer = Some(value.parse().map_err(|_| bad())?),
+                "relative_base" => relative_base = Some(value.parse().map_err(|_| bad())?),
+                "return_code" if value == "none" => return_code = Some(None),
+                "return_code" => return_code = Some(Some(value.parse().map_err(|_| bad())?)),
+                "input" => input_buffer = Some(split(value).ok_or_else(bad)?.into_iter().collect::<VecDeque<i64>>()),
+                "memory" => memory = Some(split(value).ok_or_else(bad)?),
+                "length" => match value.parse::<usize>() {
+                    Ok(n) if n == 0 || n - 1 <= max_address => length = Some(n),
+                    _ => return Err(bad())
+                },
+                "words" => match split_words(value) {
+                    Some(w) if w.iter().all(|&(address, _)| address <= max_address) => words = Some(w),
+                    _ => return Err(bad())
+                },
+                _ => return Err(bad())
+            }
+        }
+
+        let memory = match (memory, length, words) {
+            (Some(memory), _, _) => M::from_words(memory),
+            (None, Some(length), Some(words)) => {
+                let mut memory = M::from_words(vec!());
+                for (address, word) in words.into_iter().filter(|&(address, _)| address < length) {
+                    memory.write(address, word);
+                }
+                if length > 0 {
+                    let last = memory.read(length - 1);
+                    memory.write(length - 1, last);
+                }
+                memory
+            },
+            (None, None, _) => return Err(Error::MissingField("memory")),
+            (None, Some(_), None) => return Err(Error::MissingField("words"))
+        };
+        let mut program: Program<M> = Program::from_words(vec!());
+        program.memory = memory;
+        program.max_address = max_address;
+        program.set_instruction_cache(true);
+        program.instruction_pointer = instruction_pointer.ok_or(Error::MissingField("instruction_pointer"))?;
+        program.relative_base = relative_base.ok_or(Error::MissingField("relative_base"))?;
+        program.return_code = return_code.ok_or(Error::MissingField("return_code"))?;
+        program.input_buffer = input_buffer.ok_or(Error::MissingField("input"))?;
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Paged;
+    use crate::State;
+
+    #[test]
+    fn resume_test() {
+        // reads a number n and counts down from it
+        let mut program = Program::from_str("109,20,203,0,204,0,21201,0,-1,0,1205,0,4,99");
+        program.read_input(3);
+        program.read_input(17);
+        assert_eq!(program.await_output(), Ok(State::Output(3)));
+
+        let mut snapshot = vec!();
+        program.write_snapshot(&mut snapshot).unwrap();
+        let mut resumed = Program::read_snapshot(&snapshot[..]).unwrap();
+
+        for expected in [2, 1].iter() {
+            assert_eq!(program.await_output(), Ok(State::Output(*expected)));
+            assert_eq!(resumed.await_output(), Ok(State::Output(*expected)));
+        }
+        assert_eq!(resumed.input_buffer(), program.input_buffer());
+        assert_eq!(resumed.memory(), program.memory());
+        assert_eq!(resumed.await_output(), Ok(State::Done));
+        assert_eq!(resumed.input_buffer().len(), 1);
+    }
+
+    #[test]
+    fn format_test() {
+        let mut program = Program::from_str("104,-5,99");
+        program.read_input(1);
+        program.read_input(2);
+        program.step().unwrap();
+        let mut snapshot = vec!();
+        program.write_snapshot(&mut snapshot).unwrap();
+        assert_eq!(String::from_utf8(snapshot).unwrap(), "\
+intcode-snapshot 2
+instruction_pointer 2
+relative_base 0
+return_code -5
+input 1,2
+memory 104,-5,99
+");
+    }
+
+    #[test]
+    fn sparse_test() {
+        // writes 7 far beyond anything dense memory could hold
+        let mut program = Program::<Paged>::parse("1101,3,4,1099511627776,104,5,99");
+        program.set_max_address(usize::MAX);
+        assert_eq!(program.await_output(), Ok(State::Output(5)));
+        let mut snapshot = vec!();
+        program.write_snapshot(&mut snapshot).unwrap();
+        let text = String::from_utf8(snapshot).unwrap();
+        assert!(text.ends_with("length 1099511627777\nwords 0:1101,1:3,2:4,3:1099511627776,4:104,5:5,6:99,1099511627776:7\n"));
+
+        // too far out for the default maximum address, and for dense memory
+        assert_eq!(Program::read_snapshot(text.as_bytes()).map(|_| ()).map_err(|e| e.to_string()), Err("cannot parse field length on line 6".to_string()));
+        let header = "intcode-snapshot 2\ninstruction_pointer 0\nrelative_base 0\nreturn_code none\ninput\nlength 1099511627777\nwords 0:99";
+        assert_eq!(Program::read_snapshot(header.as_bytes()).map(|_| ()).map_err(|e| e.to_string()), Err("cannot parse field length on line 6".to_string()));
+        let far = "intcode-snapshot 2\ninstruction_pointer 0\nrelative_base 0\nreturn_code none\ninput\nlength 5\nwords 0:99,1099511627776:7";
+        assert_eq!(Program::read_snapshot(far.as_bytes()).map(|_| ()).map_err(|e| e.to_string()), Err("cannot parse field words on line 7".to_string()));
+
+        let resumed = Program::<Paged>::restore_with_max_address(text.as_bytes(), usize::MAX).unwrap();
+        assert_eq!(resumed.memory().len(), (1 << 40) + 1);
+        assert_eq!(resumed.memory().nonzero_words(), program.memory().nonzero_words());
+        assert_eq!(resumed.memory().page_count(), 2);
+        assert_eq!(resumed.instruction_pointer, 6);
+        assert_eq!(resumed.max_address(), usize::MAX);
+
+        // version 1 snapshots still load
+        let old = Program::read_snapshot("intcode-snapshot 1\ninstruction_pointer 0\nrelative_base 0\nreturn_code none\ninput\nmemory 99".as_bytes()).unwrap();
+        assert!(old.is_terminated());
+    }
+
+    #[test]
+    fn file_test() {
+        let path = std::env::temp_dir().join(format!("intcode-snapshot-test-{}", std::process::id()));
+        let program = Program::from_str("3,0,99");
+        program.save_snapshot(&path).unwrap();
+        let loaded = Program::load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.memory(), program.memory());
+        assert!(loaded.input_buffer().is_empty());
+    }
+
+    #[test]
+    fn error_test() {
+        let read = |s: &str| Program::read_snapshot(s.as_bytes()).map(|_| ()).map_err(|e| e.to_string());
+        assert_eq!(read(""), Err("not an intcode snapshot".to_string()));
+        assert_eq!(read("1,2,3"), Err("not an intcode snapshot".to_string()));
+        assert_eq!(read("intcode-snapshot 9"), Err("unsupported snapshot version 9".to_string()));
+        assert_eq!(read("intcode-snapshot 1\nmemory 99"), Err("snapshot has no instruction_pointer field".to_string()));
+        assert_eq!(read("intcode-snapshot 1\nrelative_base x"), Err("cannot parse field relative_base on line 2".to_string()));
+        assert_eq!(read("intcode-snapshot 1\nmemory 1,,2"), Err("cannot parse field memory on line 2".to_string()));
+        assert_eq!(read("intcode-snapshot 2\nlength 5\nwords 1:2,3"), Err("cannot parse field words on line 3".to_string()));
+    }
+}
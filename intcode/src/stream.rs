@@ -0,0 +1,178 @@
+// Iterator and io adapters for a machine's input and output. Outputs are pulled lazily: each call to
+// `next` runs the machine until it produces a value. Iteration ends, without consuming anything,
+// when the machine needs more input or halts, so a host can feed it and start iterating again.
+
+use std::io;
+
+use crate::memory::Memory;
+use crate::{Error, Program, State};
+
+pub struct Outputs<'a, M: Memory = Vec<i64>> {
+    program: &'a mut Program<M>,
+    failed: bool
+}
+
+impl<'a, M: Memory> Outputs<'a, M> {
+    // Groups the outputs into vectors of `size`; the last one is shorter if the machine stopped part
+    // way through a group. A machine that fails part way through a group yields the outputs it did
+    // produce, then the error.
+    pub fn chunks(self, size: usize) -> Chunks<'a, M> {
+        assert!(size > 0, "chunk size must be positive");
+        Chunks { outputs: self, size, error: None }
+    }
+}
+
+impl<'a, M: Memory> Iterator for Outputs<'a, M> {
+    type Item = Result<i64, Error>;
+
+    fn next(&mut self) -> Option<Result<i64, Error>> {
+        if self.failed {
+            return None
+        }
+        loop {
+            match self.program.await_output() {
+                Ok(State::Output(x)) | Ok(State::OutputAwaitingInput(x)) => return Some(Ok(x)),
+                Ok(State::Running) => continue,
+                Ok(State::AwaitingInput) | Ok(State::Done) => return None,
+                Ok(state) => {
+                    self.failed = true;
+                    return Some(Err(self.program.interrupted(&state)))
+                },
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e))
+                }
+            }
+        }
+    }
+}
+
+pub struct Chunks<'a, M: Memory = Vec<i64>> {
+    outputs: Outputs<'a, M>,
+    size: usize,
+    // Held back while the partial chunk before it is returned
+    error: Option<Error>
+}
+
+impl<'a, M: Memory> Iterator for Chunks<'a, M> {
+    type Item = Result<Vec<i64>, Error>;
+
+    fn next(&mut self) -> Option<Result<Vec<i64>, Error>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e))
+        }
+        let mut chunk = Vec::with_capacity(self.size);
+        while chunk.len() < self.size {
+            match self.outputs.next() {
+                Some(Ok(x)) => chunk.push(x),
+                Some(Err(e)) if chunk.is_empty() => return Some(Err(e)),
+                Some(Err(e)) => {
+                    self.error = Some(e);
+                    break
+                },
+                None if chunk.is_empty() => return None,
+                None => break
+            }
+        }
+        Some(Ok(chunk))
+    }
+}
+
+// Queues each byte written as an input; fails without queueing anything if a byte is not ASCII
+pub struct AsciiInput<'a, M: Memory = Vec<i64>> {
+    program: &'a mut Program<M>
+}
+
+impl<'a, M: Memory> io::Write for AsciiInput<'a, M> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(b) = buf.iter().find(|b| !b.is_ascii()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("cannot send non-ASCII byte {:#04x}", b)))
+        }
+        for &b in buf {
+            self.program.read_input(b as i64);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<M: Memory> Program<M> {
+    pub fn outputs(&mut self) -> Outputs<'_, M> {
+        Outputs { program: self, failed: false }
+    }
+
+    pub fn feed<I: IntoIterator<Item = i64>>(&mut self, inputs: I) {
+        for input in inputs {
+            self.read_input(input);
+        }
+    }
+
+    pub fn ascii_input(&mut self) -> AsciiInput<'_, M> {
+        AsciiInput { program: self }
+    }
+
+    // Queues everything the reader yields as ASCII input, and returns how many bytes that was
+    pub fn feed_ascii<R: io::Read>(&mut self, mut reader: R) -> io::Result<u64> {
+        io::copy(&mut reader, &mut self.ascii_input())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // 0: IN [13]; 2: OUT [13]; 4: OUT #1; 6: OUT #2; 8: JT #1, #0; then [13] is the scratch word
+    const ECHO: &str = "3,13,4,13,104,1,104,2,1105,1,0,99,0,0";
+
+    #[test]
+    fn outputs_test() {
+        let mut program = Program::from_str(ECHO);
+        program.feed(vec!(7, 8));
+        assert_eq!(program.outputs().collect::<Result<Vec<i64>, Error>>(), Ok(vec!(7, 1, 2, 8, 1, 2)));
+        assert_eq!(program.outputs().next(), None);
+
+        // iteration picks up again once there is more input
+        program.feed(Some(9));
+        let chunks: Vec<Result<Vec<i64>, Error>> = program.outputs().chunks(2).collect();
+        assert_eq!(chunks, [Ok(vec!(9, 1)), Ok(vec!(2))]);
+    }
+
+    #[test]
+    fn error_test() {
+        let mut program = Program::from_str("104,1,104,2,104,3,99");
+        program.set_fuel(Some(2));
+        let mut outputs = program.outputs();
+        assert_eq!(outputs.next(), Some(Ok(1)));
+        assert_eq!(outputs.next(), Some(Ok(2)));
+        assert_eq!(outputs.next(), Some(Err(Error::OutOfFuel { instruction_pointer: 4, opcode: 104 })));
+        assert_eq!(outputs.next(), None);
+
+        // the outputs before a failure part way through a chunk are kept
+        let mut program = Program::from_str("104,5,104,6,42");
+        let mut chunks = program.outputs().chunks(3);
+        assert_eq!(chunks.next(), Some(Ok(vec!(5, 6))));
+        assert_eq!(chunks.next(), Some(Err(Error::UnknownOpcode { instruction_pointer: 4, opcode: 42 })));
+        assert_eq!(chunks.next(), None);
+
+        let mut program = Program::from_str("104,5,104,6,42");
+        let mut chunks = program.outputs().chunks(2);
+        assert_eq!(chunks.next(), Some(Ok(vec!(5, 6))));
+        assert_eq!(chunks.next(), Some(Err(Error::UnknownOpcode { instruction_pointer: 4, opcode: 42 })));
+        assert_eq!(chunks.next(), None);
+    }
+
+    #[test]
+    fn ascii_input_test() {
+        let mut program = Program::from_str(ECHO);
+        assert_eq!(program.feed_ascii("hi\n".as_bytes()).unwrap(), 3);
+        assert_eq!(program.input_buffer().iter().copied().collect::<Vec<i64>>(), [104, 105, 10]);
+
+        let err = program.ascii_input().write_all("é".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(program.input_buffer().len(), 3);
+    }
+}
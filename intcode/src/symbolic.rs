@@ -0,0 +1,446 @@
+// Solves for inputs by concolic execution. Some memory cells or inputs are made symbolic, each
+// with a range of values to search; the program runs concretely while every cell computed from
+// them also carries an expression in terms of them, through ADD, MUL, LT and EQ. Each JT/JF that
+// tests such a cell adds its outcome to the path condition.
+//
+// To reach a target, the solver asks for values that satisfy the target's condition along the
+// path just taken, and failing that, for values that take each branch of the path the other way,
+// as DART does. Where a symbolic value is used as an opcode, an address or a jump target, its
+// concrete value is used and nothing is recorded, so solutions are only candidates until a
+// concrete run confirms them. Conditions are solved by bisecting the ranges, pruned by interval
+// arithmetic.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::trace::Event;
+use crate::{Instruction, ParameterMode, Program};
+
+// Expressions larger than this are replaced by their concrete values
+const MAX_EXPRESSION: usize = 1000;
+// Search nodes allowed for each condition solved
+const MAX_NODES: usize = 100_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Symbol {
+    Memory(usize),
+    // The index-th input read
+    Input(usize)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    // The cell holds the value once the program halts
+    Memory(usize, i64),
+    // The index-th output has the value
+    Output(usize, i64),
+    // The instruction at the address is executed
+    Address(usize)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Solution {
+    // In the order the symbols were declared
+    pub values: Vec<i64>,
+    pub runs: usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Op {
+    Add,
+    Mul,
+    LessThan,
+    Equals
+}
+
+#[derive(Debug)]
+enum Expr {
+    Const(i64),
+    Var(usize),
+    Binary { op: Op, left: Rc<Expr>, right: Rc<Expr>, size: usize }
+}
+
+impl Expr {
+    fn size(&self) -> usize {
+        match self {
+            Expr::Binary { size, .. } => *size,
+            _ => 1
+        }
+    }
+
+    fn vars(&self, vars: &mut HashSet<usize>) {
+        match self {
+            Expr::Const(_) => (),
+            Expr::Var(var) => { vars.insert(*var); },
+            Expr::Binary { left, right, .. } => {
+                left.vars(vars);
+                right.vars(vars);
+            }
+        }
+    }
+
+    // The least and greatest values over the given ranges of the variables
+    fn bounds(&self, ranges: &[(i64, i64)]) -> (i128, i128) {
+        match self {
+            Expr::Const(c) => (*c as i128, *c as i128),
+            Expr::Var(var) => (ranges[*var].0 as i128, ranges[*var].1 as i128),
+            Expr::Binary { op, left, right, .. } => {
+                let (a, b) = (left.bounds(ranges), right.bounds(ranges));
+                match op {
+                    Op::Add => (a.0.saturating_add(b.0), a.1.saturating_add(b.1)),
+                    Op::Mul => {
+                        let products = [a.0.saturating_mul(b.0), a.0.saturating_mul(b.1), a.1.saturating_mul(b.0), a.1.saturating_mul(b.1)];
+                        (*products.iter().min().unwrap(), *products.iter().max().unwrap())
+                    },
+                    Op::LessThan if a.1 < b.0 => (1, 1),
+                    Op::LessThan if a.0 >= b.1 => (0, 0),
+                    Op::Equals if a.0 == a.1 && b.0 == b.1 && a.0 == b.0 => (1, 1),
+                    Op::Equals if a.1 < b.0 || b.1 < a.0 => (0, 0),
+                    Op::LessThan | Op::Equals => (0, 1)
+                }
+            }
+        }
+    }
+}
+
+// Combines two parameters, either of which may be concrete; None if both are
+fn binary(op: Op, left: (Option<&Rc<Expr>>, i64), right: (Option<&Rc<Expr>>, i64)) -> Option<Rc<Expr>> {
+    let operand = |(expr, value): (Option<&Rc<Expr>>, i64)| expr.cloned().unwrap_or_else(|| Rc::new(Expr::Const(value)));
+    match (op, left, right) {
+        (_, (None, _), (None, _)) => None,
+        (Op::Add, (Some(expr), _), (None, 0)) | (Op::Add, (None, 0), (Some(expr), _)) => Some(expr.clone()),
+        (Op::Mul, (Some(expr), _), (None, 1)) | (Op::Mul, (None, 1), (Some(expr), _)) => Some(expr.clone()),
+        _ => {
+            let (left, right) = (operand(left), operand(right));
+            let size = left.size().saturating_add(right.size()).saturating_add(1);
+            if size > MAX_EXPRESSION {
+                None
+            } else {
+                Some(Rc::new(Expr::Binary { op, left, right, size }))
+            }
+        }
+    }
+}
+
+// The expression is nonzero, or zero
+#[derive(Clone, Debug)]
+struct Condition {
+    expr: Rc<Expr>,
+    nonzero: bool
+}
+
+impl Condition {
+    fn negated(&self) -> Condition {
+        Condition { expr: self.expr.clone(), nonzero: !self.nonzero }
+    }
+
+    // Whether the condition can hold, and whether it must, over the ranges
+    fn check(&self, ranges: &[(i64, i64)]) -> (bool, bool) {
+        let (lo, hi) = self.expr.bounds(ranges);
+        if self.nonzero {
+            (lo != 0 || hi != 0, lo > 0 || hi < 0)
+        } else {
+            (lo <= 0 && 0 <= hi, lo == 0 && hi == 0)
+        }
+    }
+}
+
+// What one concrete run found
+struct Run {
+    path: Vec<Condition>,
+    // The condition for hitting the target along this path, when there is one
+    goal: Option<Condition>,
+    reached: bool
+}
+
+pub struct Solver {
+    program: Program,
+    inputs: Vec<i64>,
+    symbols: Vec<(Symbol, RangeInclusive<i64>)>,
+    fuel: u64,
+    max_runs: usize
+}
+
+impl Solver {
+    pub fn new(program: &Program) -> Solver {
+        Solver { program: program.clone(), inputs: vec!(), symbols: vec!(), fuel: 1_000_000, max_runs: 1000 }
+    }
+
+    // The concrete inputs queued for every run; symbolic inputs replace some of them
+    pub fn set_inputs(&mut self, inputs: &[i64]) {
+        self.inputs = inputs.to_vec();
+    }
+
+    pub fn symbolic(&mut self, symbol: Symbol, range: RangeInclusive<i64>) {
+        if let Symbol::Input(index) = symbol {
+            if index >= self.inputs.len() {
+                self.inputs.resize(index + 1, 0);
+            }
+        }
+        self.symbols.push((symbol, range));
+    }
+
+    // Steps allowed in each run
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = fuel;
+    }
+
+    pub fn set_max_runs(&mut self, max_runs: usize) {
+        self.max_runs = max_runs;
+    }
+
+    // Values for the symbols that reach the target, or None if the runs ran out first
+    pub fn solve(&self, target: Target) -> Option<Solution> {
+        let start: Vec<i64> = self.symbols.iter().map(|(_, range)| *range.start()).collect();
+        // Values still to run, and how much of their path was already explored
+        let mut pending = vec!((start, 0));
+        let mut tried = HashSet::new();
+        let mut runs = 0;
+        while let Some((values, bound)) = pending.pop() {
+            if runs == self.max_runs {
+                break
+            }
+            if !tried.insert(values.clone()) {
+                continue
+            }
+            runs += 1;
+            let run = self.execute(&values, target);
+            if run.reached {
+                return Some(Solution { values, runs })
+            }
+            for branch in bound..run.path.len() {
+                let mut conditions = run.path[..branch].to_vec();
+                conditions.push(run.path[branch].negated());
+                if let Some(next) = self.search(&conditions, &values) {
+                    pending.push((next, branch + 1));
+                }
+            }
+            // tried first, as it keeps to the path
+            if let Some(goal) = run.goal {
+                let mut conditions = run.path.clone();
+                conditions.push(goal);
+                if let Some(next) = self.search(&conditions, &values) {
+                    pending.push((next, run.path.len()));
+                }
+            }
+        }
+        None
+    }
+
+    fn execute(&self, values: &[i64], target: Target) -> Run {
+        let mut program = self.program.clone();
+        let mut inputs = self.inputs.clone();
+        let mut cells: HashMap<usize, Rc<Expr>> = HashMap::new();
+        let mut input_vars: HashMap<usize, usize> = HashMap::new();
+        for (var, (symbol, _)) in self.symbols.iter().enumerate() {
+            match *symbol {
+                Symbol::Memory(address) => {
+                    program.overwrite_memory(address, values[var]);
+                    cells.insert(address, Rc::new(Expr::Var(var)));
+                },
+                Symbol::Input(index) => {
+                    inputs[index] = values[var];
+                    input_vars.insert(index, var);
+                }
+            }
+        }
+        for input in &inputs {
+            program.read_input(*input);
+        }
+
+        let mut run = Run { path: vec!(), goal: None, reached: false };
+        let mut inputs_read = 0;
+        let mut outputs = 0;
+        let mut halted = false;
+        for _ in 0..self.fuel {
+            let ip = program.instruction_pointer;
+            if target == Target::Address(ip) {
+                run.reached = true;
+                break
+            }
+            let instruction = match program.current_instruction() {
+                Ok(Instruction::Halt) => {
+                    halted = true;
+                    break
+                },
+                Ok(instruction) => instruction,
+                Err(_) => break
+            };
+            // the expressions of the parameters read, taken before the instruction can overwrite them
+            let sources: Vec<Option<Rc<Expr>>> = instruction.parameter_modes().iter().enumerate().map(|(i, mode)| {
+                let address = match mode {
+                    ParameterMode::Immediate => (ip + 1 + i) as i64,
+                    ParameterMode::Positional => program.peek(ip + 1 + i),
+                    // an overflow fails the step, so there is nothing to read
+                    ParameterMode::Relative => program.relative_base.checked_add(program.peek(ip + 1 + i))?,
+                    ParameterMode::Extension(_) => return None
+                };
+                usize::try_from(address).ok().and_then(|address| cells.get(&address).cloned())
+            }).collect();
+            let mut event = None;
+            if program.step_observed(&mut |e: &Event| event = Some(e.clone())).is_err() {
+                break
+            }
+            let event = match event {
+                Some(event) => event,
+                None => break
+            };
+
+            let op = match instruction {
+                Instruction::Add { .. } => Some(Op::Add),
+                Instruction::Mult { .. } => Some(Op::Mul),
+                Instruction::LessThan { .. } => Some(Op::LessThan),
+                Instruction::Equals { .. } => Some(Op::Equals),
+                _ => None
+            };
+            let written = match (op, event.operands) {
+                (Some(op), [Some(x), Some(y), _]) => binary(op, (sources[0].as_ref(), x), (sources[1].as_ref(), y)),
+                _ if event.input.is_some() => input_vars.get(&inputs_read).map(|&var| Rc::new(Expr::Var(var))),
+                _ => None
+            };
+            if event.input.is_some() {
+                inputs_read += 1;
+            }
+            if let Some(write) = event.write {
+                match written {
+                    Some(expr) => cells.insert(write.address, expr),
+                    None => cells.remove(&write.address)
+                };
+            }
+            if let (Instruction::JumpIfTrue { .. } | Instruction::JumpIfFalse { .. }, Some(test)) = (instruction, &sources[0]) {
+                run.path.push(Condition { expr: test.clone(), nonzero: event.operands[0] != Some(0) });
+            }
+            if let Some(out) = event.output {
+                if let Target::Output(index, value) = target {
+                    if index == outputs {
+                        run.reached = out == value;
+                        let expr = sources[0].clone().unwrap_or_else(|| Rc::new(Expr::Const(out)));
+                        run.goal = binary(Op::Equals, (Some(&expr), out), (None, value)).map(|expr| Condition { expr, nonzero: true });
+                        break
+                    }
+                }
+                outputs += 1;
+            }
+        }
+
+        if let (Target::Memory(address, value), true) = (target, halted) {
+            run.reached = program.peek(address) == value;
+            let expr = cells.get(&address).cloned().unwrap_or_else(|| Rc::new(Expr::Const(program.peek(address))));
+            run.goal = binary(Op::Equals, (Some(&expr), 0), (None, value)).map(|expr| Condition { expr, nonzero: true });
+        }
+        run
+    }
+
+    // Values within the ranges satisfying all the conditions. Symbols the conditions do not
+    // mention keep their current values.
+    fn search(&self, conditions: &[Condition], values: &[i64]) -> Option<Vec<i64>> {
+        fn bisect(conditions: &[Condition], ranges: &mut Vec<(i64, i64)>, nodes: &mut usize) -> bool {
+            if *nodes == 0 {
+                return false
+            }
+            *nodes -= 1;
+            let mut decided = true;
+            for condition in conditions {
+                match condition.check(ranges) {
+                    (false, _) => return false,
+                    (true, must) => decided &= must
+                }
+            }
+            if decided {
+                return true
+            }
+            let widest = (0..ranges.len())
+                .filter(|&var| ranges[var].0 < ranges[var].1)
+                .max_by_key(|&var| ranges[var].1 as i128 - ranges[var].0 as i128);
+            let var = match widest {
+                Some(var) => var,
+                None => return false
+            };
+            let (lo, hi) = ranges[var];
+            let mid = ((lo as i128 + hi as i128).div_euclid(2)) as i64;
+            for half in [(lo, mid), (mid + 1, hi)] {
+                ranges[var] = half;
+                if bisect(conditions, ranges, nodes) {
+                    return true
+                }
+            }
+            ranges[var] = (lo, hi);
+            false
+        }
+
+        let mut vars = HashSet::new();
+        for condition in conditions {
+            condition.expr.vars(&mut vars);
+        }
+        let mut ranges: Vec<(i64, i64)> = self.symbols.iter().enumerate().map(|(var, (_, range))| {
+            if vars.contains(&var) { (*range.start(), *range.end()) } else { (values[var], values[var]) }
+        }).collect();
+        let mut nodes = MAX_NODES;
+        if bisect(conditions, &mut ranges, &mut nodes) {
+            Some(ranges.iter().map(|range| range.0).collect())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shaped like a day 2 program: memory[0] ends up as 3 * noun + verb + 1, after the first
+    // instruction has used noun and verb as addresses
+    const GRAVITY: &str = "1,0,0,3,2,1,17,3,1,3,2,3,1,3,18,0,99,3,1";
+
+    #[test]
+    fn noun_verb_test() {
+        let program = Program::from_str(GRAVITY);
+        let mut solver = Solver::new(&program);
+        solver.symbolic(Symbol::Memory(1), 0..=99);
+        solver.symbolic(Symbol::Memory(2), 0..=99);
+        let solution = solver.solve(Target::Memory(0, 250)).unwrap();
+        let (noun, verb) = (solution.values[0], solution.values[1]);
+
+        let mut program = program.clone();
+        program.overwrite_memory(1, noun);
+        program.overwrite_memory(2, verb);
+        program.run(&[], |_| ()).unwrap();
+        assert_eq!(program.peek(0), 250);
+        assert!(solution.runs <= 3);
+
+        assert_eq!(solver.solve(Target::Memory(0, 1_000_000)), None);
+    }
+
+    // from day05_tests: outputs 999 below 8, 1000 at 8 and 1001 above
+    const COMPARE: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+
+    #[test]
+    fn branch_test() {
+        let program = Program::from_str(COMPARE);
+        let mut solver = Solver::new(&program);
+        solver.symbolic(Symbol::Input(0), -100..=100);
+
+        let below = solver.solve(Target::Output(0, 999)).unwrap();
+        assert!(below.values[0] < 8);
+        assert_eq!(solver.solve(Target::Output(0, 1000)).unwrap().values, [8]);
+        let above = solver.solve(Target::Output(0, 1001)).unwrap();
+        assert!(above.values[0] > 8);
+        assert_eq!(solver.solve(Target::Output(0, 1002)), None);
+        assert_eq!(solver.solve(Target::Address(31)).map(|s| s.values[0] < 8), Some(true));
+    }
+
+    #[test]
+    fn equation_test() {
+        // outputs 1 if x * y + 3 == 1003 and x < y, else 0
+        let program = Program::from_str("3,100,3,101,2,100,101,102,1001,102,3,102,1008,102,1003,103,7,100,101,104,2,103,104,103,4,103,99");
+        let mut solver = Solver::new(&program);
+        solver.symbolic(Symbol::Input(0), 0..=1000);
+        solver.symbolic(Symbol::Input(1), 0..=1000);
+        let solution = solver.solve(Target::Output(0, 1)).unwrap();
+        let (x, y) = (solution.values[0], solution.values[1]);
+        assert_eq!(x * y, 1000);
+        assert!(x < y);
+    }
+}
@@ -0,0 +1,173 @@
+use std::io;
+
+use crate::Instruction;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Write {
+    pub address: usize,
+    pub old: i64,
+    pub new: i64
+}
+
+// One executed instruction, reported after it has taken effect
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Event {
+    pub address: usize,
+    pub opcode: i64,
+    pub instruction: Instruction,
+    // The value read for each parameter; a destination parameter reports the address written to.
+    // The target of a jump that is not taken is never read, and stays None.
+    pub operands: [Option<i64>; 3],
+    pub write: Option<Write>,
+    pub relative_base: Option<(i64, i64)>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+    pub next: usize
+}
+
+impl Event {
+    pub(crate) fn new(address: usize, opcode: i64, instruction: Instruction) -> Event {
+        Event {
+            address,
+            opcode,
+            instruction,
+            operands: [None; 3],
+            write: None,
+            relative_base: None,
+            input: None,
+            output: None,
+            next: address
+        }
+    }
+
+    pub(crate) fn record_write(&mut self, sources: [i64; 2], write: Write) {
+        self.operands = [Some(sources[0]), Some(sources[1]), Some(write.address as i64)];
+        self.write = Some(write);
+    }
+
+    pub fn operands(&self) -> &[Option<i64>] {
+        &self.operands[..self.instruction.arity()]
+    }
+
+    pub fn to_json(&self, step: u64) -> String {
+        fn opt(x: Option<i64>) -> String {
+            x.map(|x| x.to_string()).unwrap_or_else(|| "null".to_string())
+        }
+        let operands: Vec<String> = self.operands().iter().map(|x| opt(*x)).collect();
+        let write = match self.write {
+            None => "null".to_string(),
+            Some(w) => format!("{{\"address\":{},\"old\":{},\"new\":{}}}", w.address, w.old, w.new)
+        };
+        let relative_base = match self.relative_base {
+            None => "null".to_string(),
+            Some((old, new)) => format!("{{\"old\":{},\"new\":{}}}", old, new)
+        };
+        format!("{{\"step\":{},\"ip\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"operands\":[{}],\"write\":{},\"rb\":{},\"input\":{},\"output\":{},\"next\":{}}}",
+            step, self.address, self.opcode, self.instruction.mnemonic(), operands.join(","),
+            write, relative_base, opt(self.input), opt(self.output), self.next)
+    }
+}
+
+pub trait Observer {
+    fn on_step(&mut self, event: &Event);
+}
+
+impl Observer for () {
+    fn on_step(&mut self, _event: &Event) {}
+}
+
+// Both observers see every step
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn on_step(&mut self, event: &Event) {
+        self.0.on_step(event);
+        self.1.on_step(event);
+    }
+}
+
+impl<F: FnMut(&Event)> Observer for F {
+    fn on_step(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+// Writes one JSON object per executed instruction. The first write error stops the trace and
+// is returned by `finish`.
+pub struct JsonlWriter<W: io::Write> {
+    writer: W,
+    steps: u64,
+    error: Option<io::Error>
+}
+
+impl<W: io::Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> JsonlWriter<W> {
+        JsonlWriter { writer, steps: 0, error: None }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.writer.flush().map(|_| self.writer)
+        }
+    }
+}
+
+impl<W: io::Write> Observer for JsonlWriter<W> {
+    fn on_step(&mut self, event: &Event) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", event.to_json(self.steps)) {
+                self.error = Some(e);
+            }
+        }
+        self.steps += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParameterMode, Program, State};
+
+    #[test]
+    fn event_test() {
+        let mut program = Program::from_str("3,9,1002,9,3,9,4,9,99,0");
+        program.read_input(7);
+        let mut events = vec!();
+        program.run_observed(&[], |_| (), &mut |e: &Event| events.push(e.clone())).unwrap();
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].input, Some(7));
+        assert_eq!(events[0].write, Some(Write { address: 9, old: 0, new: 7 }));
+        assert_eq!(events[1].instruction, Instruction::Mult { m1: ParameterMode::Positional, m2: ParameterMode::Immediate, m3: ParameterMode::Positional });
+        assert_eq!(events[1].operands(), [Some(7), Some(3), Some(9)]);
+        assert_eq!(events[1].write, Some(Write { address: 9, old: 7, new: 21 }));
+        assert_eq!(events[2].output, Some(21));
+        assert_eq!((events[2].address, events[2].next), (6, 8));
+        assert_eq!(events[3].opcode, 99);
+    }
+
+    #[test]
+    fn jump_and_relative_base_test() {
+        let mut program = Program::from_str("109,5,1106,1,0,1105,1,9,0,99");
+        let mut events = vec!();
+        program.await_output_observed(&mut |e: &Event| events.push(e.clone())).unwrap();
+
+        assert_eq!(events[0].relative_base, Some((0, 5)));
+        assert_eq!(events[1].operands(), [Some(1), None]);
+        assert_eq!(events[1].next, 5);
+        assert_eq!(events[2].operands(), [Some(1), Some(9)]);
+        assert_eq!(events[2].next, 9);
+    }
+
+    #[test]
+    fn jsonl_test() {
+        let mut program = Program::from_str("1101,2,3,5,104,0,99");
+        let mut writer = JsonlWriter::new(vec!());
+        assert_eq!(program.step_observed(&mut writer), Ok(State::Running));
+        assert_eq!(program.step_observed(&mut writer), Ok(State::Output(5)));
+        let trace = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(trace.lines().collect::<Vec<&str>>(), [
+            r#"{"step":0,"ip":0,"opcode":1101,"mnemonic":"ADD","operands":[2,3,5],"write":{"address":5,"old":0,"new":5},"rb":null,"input":null,"output":null,"next":4}"#,
+            r#"{"step":1,"ip":4,"opcode":104,"mnemonic":"OUT","operands":[5],"write":null,"rb":null,"input":null,"output":5,"next":6}"#
+        ]);
+    }
+}
@@ -0,0 +1,441 @@
+// Ahead-of-time translation of an intcode program into standalone Rust source.
+//
+// The generated `Machine` runs a match on the instruction pointer, with one arm per instruction
+// found by the disassembler's linear sweep, and a comment giving each arm's disassembly. Any other
+// address, or an instruction whose words have been overwritten, falls back to an embedded
+// interpreter, so self-modifying programs still behave. The machine offers the same interface as
+// `Program`: read_input, await_output, run and is_terminated. Only the standard instruction set is
+// translated; a program's dialect is ignored, and arithmetic is always checked, as it is by
+// default in `Program`.
+
+use crate::disasm::{self, Item};
+use crate::memory::Memory;
+use crate::{Instruction, ParameterMode, Program};
+
+const PRELUDE: &str = r#"#![allow(dead_code, unused_parens, clippy::all)]
+
+use std::collections::VecDeque;
+use std::fmt;
+
+const MAX_ADDRESS: i64 = (1 << 24) - 1;
+
+#[derive(PartialEq, Debug)]
+pub enum State {
+    Output(i64),
+    AwaitingInput,
+    Done
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub instruction_pointer: usize,
+    pub message: &'static str
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, writer: &mut fmt::Formatter) -> fmt::Result {
+        write!(writer, "{} (at {})", self.message, self.instruction_pointer)
+    }
+}
+
+pub struct Machine {
+    memory: Vec<i64>,
+    ip: usize,
+    rb: i64,
+    input: VecDeque<i64>,
+    last_output: Option<i64>,
+    // Set once any word of compiled code is overwritten
+    modified: bool,
+    dirty: Vec<bool>
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        Machine {
+            memory: IMAGE.to_vec(),
+            ip: 0,
+            rb: 0,
+            input: VecDeque::new(),
+            last_output: None,
+            modified: false,
+            dirty: vec![false; IMAGE.len()]
+        }
+    }
+
+    pub fn read_input(&mut self, input: i64) {
+        self.input.push_back(input)
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.peek(self.ip) == 99
+    }
+
+    pub fn run<F: FnMut(i64)>(&mut self, inputs: &[i64], mut on_output: F) -> Result<Option<i64>, Error> {
+        for input in inputs {
+            self.read_input(*input);
+        }
+        loop {
+            match self.await_output()? {
+                State::Output(x) => on_output(x),
+                State::AwaitingInput => return Err(self.error("input requested but none is queued")),
+                State::Done => return Ok(self.last_output)
+            }
+        }
+    }
+
+    fn error(&self, message: &'static str) -> Error {
+        Error { instruction_pointer: self.ip, message }
+    }
+
+    fn peek(&self, address: usize) -> i64 {
+        self.memory.get(address).copied().unwrap_or(0)
+    }
+
+    fn address(&self, address: i64) -> Result<usize, Error> {
+        match address {
+            a if a < 0 => Err(self.error("negative address")),
+            a if a > MAX_ADDRESS => Err(self.error("address out of range")),
+            a => Ok(a as usize)
+        }
+    }
+
+    fn load(&self, address: i64) -> Result<i64, Error> {
+        self.address(address).map(|a| self.peek(a))
+    }
+
+    fn store(&mut self, address: i64, value: i64) -> Result<(), Error> {
+        let address = self.address(address)?;
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        if self.memory[address] != value && CODE.get(address) == Some(&1) {
+            self.modified = true;
+            self.dirty[address] = true;
+        }
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    fn add(&self, x: i64, y: i64) -> Result<i64, Error> {
+        x.checked_add(y).ok_or_else(|| self.error("arithmetic overflow"))
+    }
+
+    fn mul(&self, x: i64, y: i64) -> Result<i64, Error> {
+        x.checked_mul(y).ok_or_else(|| self.error("arithmetic overflow"))
+    }
+
+    fn relative(&self, word: i64) -> Result<i64, Error> {
+        self.add(self.rb, word)
+    }
+
+    fn target(&self, target: i64) -> Result<usize, Error> {
+        if target < 0 {
+            return Err(self.error("invalid jump target"))
+        }
+        Ok(target as usize)
+    }
+
+    // Whether the compiled instruction at `address` still matches memory
+    fn clean(&self, address: usize, width: usize) -> bool {
+        !self.modified || self.dirty[address..address + width].iter().all(|d| !d)
+    }
+
+    fn mode(&self, n: usize) -> Result<i64, Error> {
+        match (self.peek(self.ip) / [100, 1000, 10000][n - 1]).rem_euclid(10) {
+            m @ 0..=2 => Ok(m),
+            _ => Err(self.error("bad parameter mode"))
+        }
+    }
+
+    fn operand(&self, n: usize) -> Result<i64, Error> {
+        let word = self.peek(self.ip + n);
+        match self.mode(n)? {
+            0 => self.load(word),
+            1 => Ok(word),
+            _ => self.load(self.relative(word)?)
+        }
+    }
+
+    fn destination(&self, n: usize) -> Result<i64, Error> {
+        let word = self.peek(self.ip + n);
+        match self.mode(n)? {
+            0 => Ok(word),
+            1 => Err(self.error("write in immediate mode")),
+            _ => self.relative(word)
+        }
+    }
+
+    // Executes the instruction at ip the slow way
+    fn interpret(&mut self) -> Result<Option<State>, Error> {
+        let ip = self.ip;
+        match self.peek(ip).rem_euclid(100) {
+            99 => return Ok(Some(State::Done)),
+            op @ 1 | op @ 2 | op @ 7 | op @ 8 => {
+                let (p1, p2) = (self.operand(1)?, self.operand(2)?);
+                let value = match op {
+                    1 => self.add(p1, p2)?,
+                    2 => self.mul(p1, p2)?,
+                    7 => (p1 < p2) as i64,
+                    _ => (p1 == p2) as i64
+                };
+                let address = self.destination(3)?;
+                self.store(address, value)?;
+                self.ip = ip + 4;
+            },
+            3 => {
+                let address = self.destination(1)?;
+                match self.input.front() {
+                    None => return Ok(Some(State::AwaitingInput)),
+                    Some(&x) => self.store(address, x)?
+                }
+                self.input.pop_front();
+                self.ip = ip + 2;
+            },
+            4 => {
+                let x = self.operand(1)?;
+                self.last_output = Some(x);
+                self.ip = ip + 2;
+                return Ok(Some(State::Output(x)))
+            },
+            op @ 5 | op @ 6 => {
+                if (self.operand(1)? != 0) == (op == 5) {
+                    self.ip = self.target(self.operand(2)?)?;
+                } else {
+                    self.ip = ip + 3;
+                }
+            },
+            9 => {
+                self.rb = self.relative(self.operand(1)?)?;
+                self.ip = ip + 2;
+            },
+            _ => return Err(self.error("unknown opcode"))
+        }
+        Ok(None)
+    }
+"#;
+
+const MAIN: &str = r#"
+// Reads comma- or whitespace-separated integers from stdin as input, and prints each output
+fn main() {
+    use std::io::Read;
+
+    let mut text = String::new();
+    if let Err(e) = std::io::stdin().read_to_string(&mut text) {
+        eprintln!("Error reading input: {}", e);
+        std::process::exit(1);
+    }
+    let mut inputs = vec!();
+    for word in text.split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty()) {
+        match word.parse() {
+            Ok(x) => inputs.push(x),
+            Err(_) => {
+                eprintln!("Not an integer: {}", word);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Err(e) = Machine::new().run(&inputs, |x| println!("{}", x)) {
+        eprintln!("Program failed: {}", e);
+        std::process::exit(1);
+    }
+}
+"#;
+
+fn operand(mode: &ParameterMode, word: i64) -> String {
+    match mode {
+        ParameterMode::Positional => format!("self.load({})?", word),
+        ParameterMode::Immediate => format!("({})", word),
+        ParameterMode::Relative => format!("self.load(self.relative({})?)?", word),
+        ParameterMode::Extension(_) => unreachable!("the disassembler decodes standard instructions only")
+    }
+}
+
+fn destination(mode: &ParameterMode, word: i64) -> Option<String> {
+    match mode {
+        ParameterMode::Positional => Some(format!("{}", word)),
+        ParameterMode::Immediate => None,
+        ParameterMode::Relative => Some(format!("self.relative({})?", word)),
+        ParameterMode::Extension(_) => unreachable!("the disassembler decodes standard instructions only")
+    }
+}
+
+// The body of the match arm for one instruction at `address`
+fn arm(address: usize, instruction: &Instruction, params: &[i64]) -> Vec<String> {
+    let modes = instruction.parameter_modes();
+    let read = |k: usize| operand(&modes[k], params[k]);
+    let next = address + 1 + params.len();
+    let store = |k: usize, value: String| match destination(&modes[k], params[k]) {
+        Some(d) => vec!(format!("let x = {};", value), format!("self.store({}, x)?;", d), format!("self.ip = {};", next)),
+        None => vec!("return Err(self.error(\"write in immediate mode\"))".to_string())
+    };
+    match instruction {
+        Instruction::Halt => vec!("return Ok(State::Done)".to_string()),
+        Instruction::Add { .. } => store(2, format!("self.add({}, {})?", read(0), read(1))),
+        Instruction::Mult { .. } => store(2, format!("self.mul({}, {})?", read(0), read(1))),
+        Instruction::LessThan { .. } => store(2, format!("({} < {}) as i64", read(0), read(1))),
+        Instruction::Equals { .. } => store(2, format!("({} == {}) as i64", read(0), read(1))),
+        Instruction::Input { .. } => match destination(&modes[0], params[0]) {
+            Some(d) => vec!(
+                "let x = match self.input.front() {".to_string(),
+                "    Some(&x) => x,".to_string(),
+                "    None => return Ok(State::AwaitingInput)".to_string(),
+                "};".to_string(),
+                format!("self.store({}, x)?;", d),
+                "self.input.pop_front();".to_string(),
+                format!("self.ip = {};", next)
+            ),
+            None => vec!("return Err(self.error(\"write in immediate mode\"))".to_string())
+        },
+        Instruction::Output { .. } => vec!(
+            format!("let x = {};", read(0)),
+            "self.last_output = Some(x);".to_string(),
+            format!("self.ip = {};", next),
+            "return Ok(State::Output(x))".to_string()
+        ),
+        Instruction::JumpIfTrue { .. } | Instruction::JumpIfFalse { .. } => {
+            let test = if let Instruction::JumpIfTrue { .. } = instruction { "!=" } else { "==" };
+            vec!(
+                format!("if {} {} 0 {{", read(0), test),
+                format!("    self.ip = self.target({})?;", read(1)),
+                "} else {".to_string(),
+                format!("    self.ip = {};", next),
+                "}".to_string()
+            )
+        },
+        Instruction::RelativeBaseAdjust { .. } => vec!(
+            format!("self.rb = self.relative({})?;", read(0)),
+            format!("self.ip = {};", next)
+        ),
+        Instruction::Extension { .. } => unreachable!("the disassembler decodes standard instructions only")
+    }
+}
+
+// Translates a memory image into Rust source for a `Machine` that starts at address 0. With
+// `main`, the source also gets a main function and builds as a standalone binary.
+pub fn to_rust(memory: &[i64], main: bool) -> String {
+    let lines = disasm::disassemble(memory);
+    let mut code = vec![0; memory.len()];
+    let mut source = String::from("// Generated from an intcode program by intcode's transpile tool\n\n");
+    source.push_str(PRELUDE);
+    source.push_str("\n    pub fn await_output(&mut self) -> Result<State, Error> {\n");
+    source.push_str("        loop {\n            match self.ip {\n");
+    for line in &lines {
+        if let Item::Instruction(instruction, params) = &line.item {
+            for flag in code.iter_mut().skip(line.address).take(line.width()) {
+                *flag = 1;
+            }
+            source.push_str(&format!("                // {}\n", line.text()));
+            source.push_str(&format!("                {} if self.clean({}, {}) => {{\n", line.address, line.address, line.width()));
+            for statement in arm(line.address, instruction, params) {
+                source.push_str(&format!("                    {}\n", statement));
+            }
+            source.push_str("                },\n");
+        }
+    }
+    source.push_str("                _ => if let Some(state) = self.interpret()? {\n");
+    source.push_str("                    return Ok(state)\n");
+    source.push_str("                }\n            }\n        }\n    }\n}\n\n");
+
+    let words: Vec<String> = memory.iter().map(|x| x.to_string()).collect();
+    let flags: Vec<String> = code.iter().map(|x: &u8| x.to_string()).collect();
+    source.push_str(&format!("static IMAGE: [i64; {}] = [{}];\n\n", memory.len(), words.join(", ")));
+    source.push_str("// 1 for every word that belongs to a compiled instruction\n");
+    source.push_str(&format!("static CODE: [u8; {}] = [{}];\n", memory.len(), flags.join(", ")));
+    if main {
+        source.push_str(MAIN);
+    }
+    source
+}
+
+impl<M: Memory> Program<M> {
+    // Translates the program's current memory; the machine starts from address 0 with no input.
+    // Panics if memory reaches further than a listing can, as a sparse memory may: the source
+    // holds all of memory, and words left out would change what the machine does.
+    pub fn to_rust(&self, main: bool) -> String {
+        let image = self.image();
+        assert_eq!(image.len(), self.memory.len(), "memory too large to translate");
+        to_rust(&image, main)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz;
+    use std::env;
+    use std::fs;
+    use std::process::{Command, Output, Stdio};
+    use std::io::Write;
+
+    #[test]
+    fn listing_test() {
+        let source = Program::from_str("1101,2,3,5,104,0,99").to_rust(false);
+        assert!(source.contains("                // ADD #2, #3, [5]\n                0 if self.clean(0, 4) => {\n                    let x = self.add((2), (3))?;\n"));
+        assert!(source.contains("static CODE: [u8; 7] = [1, 1, 1, 1, 1, 1, 1];"));
+        assert!(!source.contains("fn main()"));
+    }
+
+    // Builds the generated source with rustc, overflow checks on, and runs it on the given input
+    fn compile_and_run(name: &str, code: &str, input: &str) -> Output {
+        let dir = env::temp_dir().join(format!("intcode-transpile-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.rs");
+        let binary = dir.join("main");
+        fs::write(&source, Program::from_str(code).to_rust(true)).unwrap();
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let status = Command::new(rustc).arg("-O").arg("-C").arg("overflow-checks=on").arg("-o").arg(&binary).arg(&source).status().unwrap();
+        assert!(status.success());
+
+        let mut child = Command::new(&binary).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        output
+    }
+
+    fn stdout(output: &Output) -> String {
+        String::from_utf8(output.stdout.clone()).unwrap()
+    }
+
+    fn interpret(code: &str, inputs: &[i64]) -> String {
+        let mut outputs = String::new();
+        Program::from_str(code).run(inputs, |x| outputs.push_str(&format!("{}\n", x))).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn compiled_test() {
+        // day 5's comparison program, and day 9's quine
+        let compare = "3,9,8,9,10,9,4,9,99,-1,8";
+        assert_eq!(stdout(&compile_and_run("compare", compare, "8")), interpret(compare, &[8]));
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        assert_eq!(stdout(&compile_and_run("quine", quine, "")), interpret(quine, &[]));
+
+        // overwrites the HLT at 4 with an OUT instruction, which the interpreter has to run
+        let modifying = "1101,0,104,4,99,42,99";
+        assert_eq!(stdout(&compile_and_run("modifying", modifying, "")), "42\n");
+
+        // squares 2^32, which fails as it does in the interpreter rather than panicking
+        let overflow = "1002,5,4294967296,5,99,4294967296";
+        let output = compile_and_run("overflow", overflow, "");
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "Program failed: arithmetic overflow (at 0)\n");
+
+        // a compiled OUT whose relative address is beyond i64
+        let relative = "109,1,204,9223372036854775807,99";
+        let output = compile_and_run("relative", relative, "");
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "Program failed: arithmetic overflow (at 2)\n");
+    }
+
+    #[test]
+    fn regression_test() {
+        // the fuzzer's finds must end in an error or halt once compiled, too
+        for (i, line) in fuzz::REGRESSIONS.iter().enumerate() {
+            let case = fuzz::Case::parse(line).unwrap();
+            let code = case.program.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",");
+            let input = case.inputs.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",");
+            let output = compile_and_run(&format!("regression{}", i), &code, &input);
+            assert!(output.status.code() == Some(0) || output.status.code() == Some(1), "{}: {:?}", line, output);
+        }
+    }
+}